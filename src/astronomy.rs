@@ -1,26 +1,141 @@
 use std::cmp::Ordering;
 use std::f64::consts::PI;
+use std::path::Path;
 
 use crate::integration::G;
 
 use glam::{DAffine3, DVec3};
-use rand::{rngs::StdRng, Rng};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use uuid::Uuid;
 
 pub const AU: f64 = 1.495978707E11;
 pub const SOLAR_MASS: f64 = 1.98847E30;
 pub const SOLAR_RADIUS: f64 = 6.957E8;
+// Spawned bodies must be at least this many combined radii away from existing ones
+const SPAWN_CLEARANCE: f64 = 10.0;
+// A bitmap here named after a body in lowercase, e.g. textures/earth.bmp, is drawn instead of its circle
+const TEXTURE_DIRECTORY: &str = "textures";
 
+// Physical properties of well-known bodies, so a system only needs to give their names and orbits
+pub struct KnownBody {
+    pub name: &'static str,
+    pub mass: f64,
+    pub radius: f64,
+    pub color: [u8; 3],
+}
+
+const KNOWN_BODIES: [KnownBody; 14] = [
+    KnownBody {
+        name: "Sun",
+        mass: SOLAR_MASS,
+        radius: SOLAR_RADIUS,
+        color: [255, 255, 0],
+    },
+    KnownBody {
+        name: "Mercury",
+        mass: 3.3011E23,
+        radius: 2439.7E3,
+        color: [255, 0, 0],
+    },
+    KnownBody {
+        name: "Venus",
+        mass: 4.8675E24,
+        radius: 6051.8E3,
+        color: [0, 255, 0],
+    },
+    KnownBody {
+        name: "Earth",
+        mass: 5.972168E24,
+        radius: 6371.0E3,
+        color: [0, 0, 255],
+    },
+    KnownBody {
+        name: "Moon",
+        mass: 7.342E22,
+        radius: 1737.4E3,
+        color: [255, 255, 255],
+    },
+    KnownBody {
+        name: "Mars",
+        mass: 6.4171E23,
+        radius: 3389.5E3,
+        color: [255, 50, 0],
+    },
+    KnownBody {
+        name: "Jupiter",
+        mass: 1.8982E27,
+        radius: 69911E3,
+        color: [216, 202, 157],
+    },
+    KnownBody {
+        name: "Saturn",
+        mass: 5.6834E26,
+        radius: 58232E3,
+        color: [191, 189, 175],
+    },
+    KnownBody {
+        name: "Uranus",
+        mass: 8.681E25,
+        radius: 25362E3,
+        color: [209, 231, 231],
+    },
+    KnownBody {
+        name: "Neptune",
+        mass: 1.02413E26,
+        radius: 24622E3,
+        color: [39, 70, 135],
+    },
+    KnownBody {
+        name: "Phobos",
+        mass: 1.0659E16,
+        radius: 11.2667E3,
+        color: [200, 200, 200],
+    },
+    KnownBody {
+        name: "Deimos",
+        mass: 1.4762E15,
+        radius: 6.2E3,
+        color: [150, 150, 150],
+    },
+    KnownBody {
+        name: "Pluto",
+        mass: 1.303E22,
+        radius: 2376.6E3,
+        color: [190, 190, 255],
+    },
+    KnownBody {
+        name: "International Space Station",
+        mass: 450.0E3,
+        radius: 100.0,
+        color: [0, 0, 160],
+    },
+];
+
+// Case-insensitive. Unknown bodies need their properties given explicitly
+pub fn known_body(name: &str) -> Option<&'static KnownBody> {
+    KNOWN_BODIES
+        .iter()
+        .find(|x| x.name.eq_ignore_ascii_case(name))
+}
+
+fn texture_path(name: &str) -> Option<String> {
+    let path = format!("{}/{}.bmp", TEXTURE_DIRECTORY, name.to_lowercase());
+    Path::new(&path).is_file().then_some(path)
+}
+
+#[derive(Clone)]
 pub enum OrbitalMethod {
     Radius(f64),
     Speed(f64),
 }
 
+#[derive(Clone)]
 pub enum Inclination {
     Random(f64),
     Fixed(f64),
 }
 
+#[derive(Clone)]
 pub struct OrbitalObject {
     pub name: String,
     pub mass: f64,
@@ -32,86 +147,175 @@ pub struct OrbitalObject {
     pub color: [u8; 3],
 }
 
+impl OrbitalObject {
+    // Mass, radius and color are filled in from the known body database
+    pub fn known(
+        name: &str,
+        method: OrbitalMethod,
+        inclination: Inclination,
+        magnification: f64,
+    ) -> Option<OrbitalObject> {
+        let body = known_body(name)?;
+
+        Some(OrbitalObject {
+            name: body.name.to_string(),
+            mass: body.mass,
+            radius: body.radius,
+            positive_y_rotation: true,
+            method,
+            inclination,
+            magnification,
+            color: body.color,
+        })
+    }
+}
+
+// Classical elements relative to a parent. The reference plane is XZ with Y pointing up.
+// Angles are in radians.
+#[derive(Debug, Clone)]
+pub struct OrbitalElements {
+    pub semi_major_axis: f64, // Negative for unbound orbits
+    pub eccentricity: f64,
+    pub inclination: f64,
+    pub longitude_of_ascending_node: f64,
+    pub argument_of_periapsis: f64,
+    pub true_anomaly: f64,
+    pub eccentricity_vector: DVec3,
+    pub angular_momentum: DVec3, // Specific angular momentum
+}
+
+impl OrbitalElements {
+    pub fn is_bound(&self) -> bool {
+        self.eccentricity < 1.0 && self.semi_major_axis > 0.0
+    }
+
+    pub fn periapsis_distance(&self) -> f64 {
+        self.semi_major_axis * (1.0 - self.eccentricity)
+    }
+
+    pub fn apoapsis_distance(&self) -> f64 {
+        self.semi_major_axis * (1.0 + self.eccentricity)
+    }
+
+    // Direction from the parent towards periapsis. Circular orbits fall back to an arbitrary direction in the orbit plane
+    pub fn periapsis_direction(&self) -> DVec3 {
+        if self.eccentricity > 1.0E-12 {
+            self.eccentricity_vector.normalize()
+        } else {
+            self.angular_momentum.any_orthonormal_vector()
+        }
+    }
+
+    // Offset from the parent at the given eccentric anomaly. Only meaningful for bound orbits
+    pub fn position_at(&self, eccentric_anomaly: f64) -> DVec3 {
+        let periapsis = self.periapsis_direction();
+        let forward = self.angular_momentum.normalize().cross(periapsis);
+        let semi_minor_axis = self.semi_major_axis * (1.0 - self.eccentricity.powi(2)).sqrt();
+
+        periapsis * self.semi_major_axis * (eccentric_anomaly.cos() - self.eccentricity)
+            + forward * semi_minor_axis * eccentric_anomaly.sin()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct AstronomicalObject {
     pub name: String,
     pub mass: f64,
+    pub mass_loss_rate: f64, // kg/s
     pub position: DVec3,
     pub velocity: DVec3,
     pub acceleration: DVec3,
     pub radius: f64,
     pub magnification: f64,
     pub color: [u8; 3],
+    pub texture: Option<String>, // Path to a bitmap drawn instead of a plain circle
+    pub scenery_parent: Option<Uuid>, // Follows a Kepler orbit around this body instead of being integrated
+    pub is_comet: bool,               // Drawn with a tail pointing away from the Sun
     pub uuid: Uuid,
 }
 
+#[cfg(test)]
+impl AstronomicalObject {
+    // White body with a radius of one meter, for tests that only care about the dynamics
+    pub fn test_body(mass: f64, position: DVec3, velocity: DVec3) -> AstronomicalObject {
+        AstronomicalObject {
+            name: String::from("Test"),
+            mass,
+            mass_loss_rate: 0.0,
+            position,
+            velocity,
+            acceleration: DVec3::ZERO,
+            radius: 1.0,
+            magnification: 1.0,
+            color: [255, 255, 255],
+            texture: None,
+            scenery_parent: None,
+            is_comet: false,
+            uuid: Uuid::new_v4(),
+        }
+    }
+}
+
 impl AstronomicalObject {
     pub fn default(rng: &mut StdRng) -> Vec<AstronomicalObject> {
+        let sun = known_body("Sun").unwrap();
         let mut system = vec![AstronomicalObject {
-            name: "Sun".to_string(),
-            mass: SOLAR_MASS,
+            name: sun.name.to_string(),
+            mass: sun.mass,
+            mass_loss_rate: 0.0,
             position: DVec3::ZERO,
             velocity: DVec3::ZERO,
             acceleration: DVec3::ZERO,
-            radius: SOLAR_RADIUS,
+            radius: sun.radius,
             magnification: 100.0,
-            color: [255, 255, 0],
+            color: sun.color,
+            texture: texture_path(sun.name),
+            scenery_parent: None,
+            is_comet: false,
             uuid: Uuid::new_v4(),
         }];
         system.push(AstronomicalObject::place_on_orbit(
-            OrbitalObject {
-                name: "Mercury".to_string(),
-                mass: 3.3011E23,
-                radius: 2439.7E3,
-                positive_y_rotation: true,
-                method: OrbitalMethod::Radius(0.387098 * AU),
-                inclination: Inclination::Fixed(7.005f64.to_radians()),
-                magnification: 2.0E7,
-                color: [255, 0, 0],
-            },
+            OrbitalObject::known(
+                "Mercury",
+                OrbitalMethod::Radius(0.387098 * AU),
+                Inclination::Fixed(7.005f64.to_radians()),
+                2.0E7,
+            )
+            .unwrap(),
             system.iter().find(|x| x.name == "Sun").unwrap(),
             rng,
         ));
         system.push(AstronomicalObject::place_on_orbit(
-            OrbitalObject {
-                name: "Venus".to_string(),
-                mass: 4.8675E24,
-                radius: 6051.8E3,
-                positive_y_rotation: true,
-                method: OrbitalMethod::Radius(0.723332 * AU),
-                inclination: Inclination::Fixed(3.39458f64.to_radians()),
-                magnification: 2.0E7,
-                color: [0, 255, 0],
-            },
+            OrbitalObject::known(
+                "Venus",
+                OrbitalMethod::Radius(0.723332 * AU),
+                Inclination::Fixed(3.39458f64.to_radians()),
+                2.0E7,
+            )
+            .unwrap(),
             system.iter().find(|x| x.name == "Sun").unwrap(),
             rng,
         ));
         system.push(AstronomicalObject::place_on_orbit(
-            OrbitalObject {
-                name: "Earth".to_string(),
-                mass: 5.972168E24,
-                radius: 6371.0E3,
-                positive_y_rotation: true,
-                method: OrbitalMethod::Radius(AU),
-                inclination: Inclination::Fixed(0.0),
-                magnification: 1.0E7,
-                color: [0, 0, 255],
-            },
+            OrbitalObject::known(
+                "Earth",
+                OrbitalMethod::Radius(AU),
+                Inclination::Fixed(0.0),
+                1.0E7,
+            )
+            .unwrap(),
             system.iter().find(|x| x.name == "Sun").unwrap(),
             rng,
         ));
         system.push(AstronomicalObject::place_on_orbit(
-            OrbitalObject {
-                name: "Moon".to_string(),
-                mass: 7.342E22,
-                radius: 1737.4E3,
-                positive_y_rotation: true,
-                method: OrbitalMethod::Radius(384399E3),
+            OrbitalObject::known(
+                "Moon",
+                OrbitalMethod::Radius(384399E3),
                 // inclination: Inclination::Fixed(0.0f64.to_radians()),
-                inclination: Inclination::Fixed(5.145f64.to_radians()),
-                magnification: 1.0E7,
-                color: [255, 255, 255],
-            },
+                Inclination::Fixed(5.145f64.to_radians()),
+                1.0E7,
+            )
+            .unwrap(),
             system.iter().find(|x| x.name == "Earth").unwrap(),
             rng,
         ));
@@ -130,131 +334,104 @@ impl AstronomicalObject {
         //     system.iter().find(|x| x.name == "Earth").unwrap(),
         // ));
         system.push(AstronomicalObject::place_on_orbit(
-            OrbitalObject {
-                name: "Mars".to_string(),
-                mass: 6.4171E23,
-                radius: 3389.5E3,
-                positive_y_rotation: true,
-                method: OrbitalMethod::Radius(1.52368055 * AU),
-                inclination: Inclination::Fixed(1.850f64.to_radians()),
-                magnification: 2.0E7,
-                color: [255, 50, 0],
-            },
+            OrbitalObject::known(
+                "Mars",
+                OrbitalMethod::Radius(1.52368055 * AU),
+                Inclination::Fixed(1.850f64.to_radians()),
+                2.0E7,
+            )
+            .unwrap(),
             system.iter().find(|x| x.name == "Sun").unwrap(),
             rng,
         ));
         system.push(AstronomicalObject::place_on_orbit(
-            OrbitalObject {
-                name: "Jupiter".to_string(),
-                mass: 1.8982E27,
-                radius: 69911E3,
-                positive_y_rotation: true,
-                method: OrbitalMethod::Radius(5.2038 * AU),
-                inclination: Inclination::Fixed(1.303f64.to_radians()),
-                magnification: 2.0E7,
-                color: [216, 202, 157],
-            },
+            OrbitalObject::known(
+                "Jupiter",
+                OrbitalMethod::Radius(5.2038 * AU),
+                Inclination::Fixed(1.303f64.to_radians()),
+                2.0E7,
+            )
+            .unwrap(),
             system.iter().find(|x| x.name == "Sun").unwrap(),
             rng,
         ));
         system.push(AstronomicalObject::place_on_orbit(
-            OrbitalObject {
-                name: "Saturn".to_string(),
-                mass: 5.6834E26,
-                radius: 58232E3,
-                positive_y_rotation: true,
-                method: OrbitalMethod::Radius(9.5826 * AU),
-                inclination: Inclination::Fixed(2.485f64.to_radians()),
-                magnification: 2.0E7,
-                color: [191, 189, 175],
-            },
+            OrbitalObject::known(
+                "Saturn",
+                OrbitalMethod::Radius(9.5826 * AU),
+                Inclination::Fixed(2.485f64.to_radians()),
+                2.0E7,
+            )
+            .unwrap(),
             system.iter().find(|x| x.name == "Sun").unwrap(),
             rng,
         ));
         system.push(AstronomicalObject::place_on_orbit(
-            OrbitalObject {
-                name: "Uranus".to_string(),
-                mass: 8.681E25,
-                radius: 25362E3,
-                positive_y_rotation: true,
-                method: OrbitalMethod::Radius(19.19126 * AU),
-                inclination: Inclination::Fixed(0.773f64.to_radians()),
-                magnification: 2.0E7,
-                color: [209, 231, 231],
-            },
+            OrbitalObject::known(
+                "Uranus",
+                OrbitalMethod::Radius(19.19126 * AU),
+                Inclination::Fixed(0.773f64.to_radians()),
+                2.0E7,
+            )
+            .unwrap(),
             system.iter().find(|x| x.name == "Sun").unwrap(),
             rng,
         ));
         system.push(AstronomicalObject::place_on_orbit(
-            OrbitalObject {
-                name: "Neptune".to_string(),
-                mass: 1.02413E26,
-                radius: 24622E3,
-                positive_y_rotation: true,
-                method: OrbitalMethod::Radius(30.07 * AU),
-                inclination: Inclination::Fixed(1.770f64.to_radians()),
-                magnification: 2.0E7,
-                color: [39, 70, 135],
-            },
+            OrbitalObject::known(
+                "Neptune",
+                OrbitalMethod::Radius(30.07 * AU),
+                Inclination::Fixed(1.770f64.to_radians()),
+                2.0E7,
+            )
+            .unwrap(),
             system.iter().find(|x| x.name == "Sun").unwrap(),
             rng,
         ));
         system.push(AstronomicalObject::place_on_orbit(
-            OrbitalObject {
-                name: "Phobos".to_string(),
-                mass: 1.0659E16,
-                radius: 11.2667E3,
-                positive_y_rotation: true,
-                method: OrbitalMethod::Radius(9376E3),
-                inclination: Inclination::Fixed(26.04f64.to_radians()),
-                magnification: 2.0E11,
-                color: [200, 200, 200],
-            },
+            OrbitalObject::known(
+                "Phobos",
+                OrbitalMethod::Radius(9376E3),
+                Inclination::Fixed(26.04f64.to_radians()),
+                2.0E11,
+            )
+            .unwrap(),
             system.iter().find(|x| x.name == "Mars").unwrap(),
             rng,
         ));
         system.push(AstronomicalObject::place_on_orbit(
-            OrbitalObject {
-                name: "Deimos".to_string(),
-                mass: 1.4762E15,
-                radius: 6.2E3,
-                positive_y_rotation: true,
-                method: OrbitalMethod::Radius(23463.2E3),
-                inclination: Inclination::Fixed(27.58f64.to_radians()),
-                magnification: 2.0E11,
-                color: [150, 150, 150],
-            },
+            OrbitalObject::known(
+                "Deimos",
+                OrbitalMethod::Radius(23463.2E3),
+                Inclination::Fixed(27.58f64.to_radians()),
+                2.0E11,
+            )
+            .unwrap(),
             system.iter().find(|x| x.name == "Mars").unwrap(),
             rng,
         ));
 
         // Never 4get
         system.push(AstronomicalObject::place_on_orbit(
-            OrbitalObject {
-                name: "Pluto".to_string(),
-                mass: 1.303E22,
-                radius: 2376.6E3,
-                positive_y_rotation: true,
-                method: OrbitalMethod::Radius(39.482 * AU),
-                inclination: Inclination::Fixed(17.16f64.to_radians()),
-                magnification: 2.0E7,
-                color: [190, 190, 255],
-            },
+            OrbitalObject::known(
+                "Pluto",
+                OrbitalMethod::Radius(39.482 * AU),
+                Inclination::Fixed(17.16f64.to_radians()),
+                2.0E7,
+            )
+            .unwrap(),
             system.iter().find(|x| x.name == "Sun").unwrap(),
             rng,
         ));
 
         system.push(AstronomicalObject::place_on_orbit(
-            OrbitalObject {
-                name: "International Space Station".to_string(),
-                mass: 450.0E3,
-                radius: 100.0,
-                positive_y_rotation: true,
-                method: OrbitalMethod::Radius(6371.0E3 + 418000.0),
-                inclination: Inclination::Fixed(51.64f64.to_radians()),
-                magnification: 1.0E7,
-                color: [0, 0, 160],
-            },
+            OrbitalObject::known(
+                "International Space Station",
+                OrbitalMethod::Radius(6371.0E3 + 418000.0),
+                Inclination::Fixed(51.64f64.to_radians()),
+                1.0E7,
+            )
+            .unwrap(),
             system.iter().find(|x| x.name == "Earth").unwrap(),
             rng,
         ));
@@ -340,17 +517,84 @@ impl AstronomicalObject {
         system
     }
 
+    // Orders by distance to target. Ties are broken by uuid so the order stays the same between frames
     pub fn cmp(&self, other: &AstronomicalObject, target: DVec3) -> Ordering {
         let a = self.position.distance_squared(target);
         let b = other.position.distance_squared(target);
 
-        if a < b {
-            return Ordering::Less;
-        } else if a > b {
-            return Ordering::Greater;
+        a.total_cmp(&b).then_with(|| self.uuid.cmp(&other.uuid))
+    }
+
+    pub fn get_orbital_elements(&self, parent: &AstronomicalObject) -> OrbitalElements {
+        let mu = G * (self.mass + parent.mass);
+        let r = self.position - parent.position;
+        let v = self.velocity - parent.velocity;
+
+        let h = r.cross(v);
+        let eccentricity_vector = v.cross(h) / mu - r.normalize();
+        let eccentricity = eccentricity_vector.length();
+        let semi_major_axis = 1.0 / (2.0 / r.length() - v.length_squared() / mu);
+
+        let inclination = (h.y / h.length()).clamp(-1.0, 1.0).acos();
+
+        // Points towards the ascending node
+        let node = DVec3::Y.cross(h);
+        let longitude_of_ascending_node = if node.length() > 0.0 {
+            (-node.z).atan2(node.x).rem_euclid(2.0 * PI)
+        } else {
+            0.0
+        };
+
+        let angle_between = |a: DVec3, b: DVec3| {
+            let angle = (a.dot(b) / (a.length() * b.length()))
+                .clamp(-1.0, 1.0)
+                .acos();
+            // Measure in the direction of motion
+            if a.cross(b).dot(h) < 0.0 {
+                2.0 * PI - angle
+            } else {
+                angle
+            }
+        };
+
+        let argument_of_periapsis = if node.length() > 0.0 && eccentricity > 0.0 {
+            angle_between(node, eccentricity_vector)
+        } else {
+            0.0
+        };
+        let true_anomaly = if eccentricity > 0.0 {
+            angle_between(eccentricity_vector, r)
+        } else {
+            0.0
+        };
+
+        OrbitalElements {
+            semi_major_axis,
+            eccentricity,
+            inclination,
+            longitude_of_ascending_node,
+            argument_of_periapsis,
+            true_anomaly,
+            eccentricity_vector,
+            angular_momentum: h,
         }
+    }
+
+    pub fn density(&self) -> f64 {
+        self.mass / (4.0 / 3.0 * PI * self.radius.powi(3))
+    }
 
-        Ordering::Equal
+    // Fluid Roche limit. Satellites of the given density closer than this are torn apart
+    pub fn roche_limit(&self, satellite_density: f64) -> f64 {
+        2.44 * self.radius * (self.density() / satellite_density).cbrt()
+    }
+
+    // Negative values mean the body is gravitationally bound to other
+    pub fn specific_orbital_energy(&self, other: &AstronomicalObject) -> f64 {
+        let distance = self.position.distance(other.position);
+        let relative_speed = self.velocity.distance(other.velocity);
+
+        relative_speed.powi(2) / 2.0 - G * (self.mass + other.mass) / distance
     }
 
     pub fn place_on_orbit(
@@ -390,20 +634,104 @@ impl AstronomicalObject {
 
         let position = transform_pos.transform_point3(DVec3::new(0.0, 0.0, radius));
         let velocity = transform_vel.transform_point3(DVec3::new(speed, 0.0, 0.0));
+        let texture = texture_path(&obj.name);
 
         AstronomicalObject {
             name: obj.name,
             mass: obj.mass,
+            mass_loss_rate: 0.0,
             position,
             velocity,
             acceleration: DVec3::ZERO,
             radius: obj.radius,
             magnification: obj.magnification,
             color: obj.color,
+            texture,
+            scenery_parent: None,
+            is_comet: false,
             uuid: Uuid::new_v4(),
         }
     }
 
+    // Random planets orbiting `center`, reproducible from the seed
+    pub fn random_cloud(
+        seed: u64,
+        count: usize,
+        center: &AstronomicalObject,
+    ) -> Vec<AstronomicalObject> {
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        (0..count)
+            .map(|_| {
+                let orbital = AstronomicalObject::get_random_planet(&mut rng);
+                AstronomicalObject::place_on_orbit(orbital, center, &mut rng)
+            })
+            .collect()
+    }
+
+    // Bodies at rest spread uniformly inside a sphere. They fall together and violently relax
+    pub fn cold_collapse(seed: u64, count: usize) -> Vec<AstronomicalObject> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let sphere_radius = 5.0 * AU;
+        let mass = 1.0E28;
+
+        let density_earth = 5.972168E24 / 6371.0E3f64.powi(3);
+        let radius = (mass / density_earth).powf(3.0_f64.recip());
+
+        (0..count)
+            .map(|i| {
+                // Rejection sampling gives a uniform distribution inside the sphere
+                let position = loop {
+                    let p = DVec3::new(
+                        rng.gen_range(-1.0..=1.0),
+                        rng.gen_range(-1.0..=1.0),
+                        rng.gen_range(-1.0..=1.0),
+                    );
+                    if p.length_squared() <= 1.0 {
+                        break p * sphere_radius;
+                    }
+                };
+
+                AstronomicalObject {
+                    name: format!("Body {}", i + 1),
+                    mass,
+                    mass_loss_rate: 0.0,
+                    position,
+                    velocity: DVec3::ZERO,
+                    acceleration: DVec3::ZERO,
+                    radius,
+                    magnification: 1.0E7,
+                    color: [
+                        rng.gen_range(100..=255),
+                        rng.gen_range(100..=255),
+                        rng.gen_range(100..=255),
+                    ],
+                    texture: None,
+                    scenery_parent: None,
+                    is_comet: false,
+                    uuid: Uuid::new_v4(),
+                }
+            })
+            .collect()
+    }
+
+    // Retries random orbit orientations until the new body is clear of all existing ones
+    pub fn place_on_orbit_clear(
+        obj: OrbitalObject,
+        target: &AstronomicalObject,
+        existing: &[AstronomicalObject],
+        attempts: usize,
+        rng: &mut StdRng,
+    ) -> Option<AstronomicalObject> {
+        (0..attempts)
+            .map(|_| AstronomicalObject::place_on_orbit(obj.clone(), target, rng))
+            .find(|new| {
+                existing.iter().all(|x| {
+                    x.position.distance(new.position) > (x.radius + new.radius) * SPAWN_CLEARANCE
+                })
+            })
+    }
+
     pub fn get_random_planet(rng: &mut StdRng) -> OrbitalObject {
         let density_earth = 5.972168E24 / 6371.0E3f64.powi(3);
         let mass = rng.gen_range(1.303E22..=6.8982E27);
@@ -5,13 +5,17 @@ use std::{
     time::{Duration, Instant},
 };
 
-use glam::{DAffine3, DVec3};
+use glam::{DAffine3, DQuat, DVec3};
 
-use crate::astronomy::AU;
+use crate::{astronomy::AU, lock::LockRecover};
 
 // For drawing camera is assumed to be situated on the positive side of the Z-axis at (0,0,1), with target being origin.
 // Matrix operations are used to transform the simulation space into camera space.
-// Camera is always kept in line with Y-axis (Y-axis is directly up). In other words camera only has pitch and yaw.
+// Camera is always kept in line with the up axis (world Y-axis by default). In other words camera only has pitch and yaw.
+// The up axis can be tilted to the system's angular momentum so that the invariable plane appears flat.
+
+const MIN_DISTANCE: f64 = 1.0;
+const MAX_DISTANCE: f64 = 5000.0 * AU;
 
 #[derive(Debug)]
 pub struct Camera {
@@ -19,10 +23,23 @@ pub struct Camera {
     pub distance: f64,
     yaw: f64,
     pitch: f64,
+    target_yaw: f64,
+    target_pitch: f64,
+    pub smoothing: f64, // Fraction of the remaining rotation left for the next frame. 0 = direct control
+    pub follow_velocity: bool, // Keep the followed body's velocity pointing right
+    pub align_to_angular_momentum: bool,
+    auto_orbit: bool,
+    pub auto_orbit_speed: f64,           // Yaw rate in rad/s
+    pub auto_orbit_pitch_amplitude: f64, // 0 keeps the pitch fixed
+    auto_orbit_base_pitch: f64,
+    auto_orbit_time: f64,
+    auto_orbit_last_update: Option<Instant>,
+    up_axis: DVec3,
     pub fov: f64,
     animation_progress: Arc<Mutex<u32>>,
     animation_start_distance: f64,
     pub animation_start: Option<DVec3>,
+    pub animation_destination: Option<(DVec3, f64)>, // Fixed point and distance when not animating towards a body
 }
 
 impl Camera {
@@ -31,40 +48,119 @@ impl Camera {
     }
     pub fn set_pitch(&mut self, angle: f64) {
         // Limit pitch between -89.9 degrees and 89.9 degrees or things go upside down.
-        self.pitch = angle.clamp(-PI / 180.0 * 89.9, PI / 180.0 * 89.9);
+        self.target_pitch = angle.clamp(-PI / 180.0 * 89.9, PI / 180.0 * 89.9);
+        if self.smoothing == 0.0 {
+            self.pitch = self.target_pitch;
+        }
     }
 
     pub fn get_yaw(&self) -> f64 {
         self.yaw
     }
     pub fn set_yaw(&mut self, angle: f64) {
-        self.yaw = angle % (2.0 * PI);
+        self.target_yaw = angle % (2.0 * PI);
+        if self.smoothing == 0.0 {
+            self.yaw = self.target_yaw;
+        }
+    }
+
+    // Eases actual orientation towards the one set by input. Called once per frame
+    pub fn update_orientation(&mut self) {
+        let step = 1.0 - self.smoothing.clamp(0.0, 1.0);
+
+        self.pitch += (self.target_pitch - self.pitch) * step;
+
+        // Take the shorter way around when yaw wraps
+        let mut yaw_difference = (self.target_yaw - self.yaw).rem_euclid(2.0 * PI);
+        if yaw_difference > PI {
+            yaw_difference -= 2.0 * PI;
+        }
+        self.yaw = (self.yaw + yaw_difference * step) % (2.0 * PI);
+    }
+
+    pub fn set_up_axis(&mut self, axis: DVec3) {
+        // Zero angular momentum has no direction, keep the previous axis
+        if let Some(axis) = axis.try_normalize() {
+            self.up_axis = axis;
+        }
+    }
+
+    // Rotates the up axis onto world Y-axis
+    pub fn get_alignment(&self) -> DAffine3 {
+        DAffine3::from_quat(DQuat::from_rotation_arc(self.up_axis, DVec3::Y))
+    }
+
+    pub fn is_auto_orbiting(&self) -> bool {
+        self.auto_orbit
+    }
+    pub fn set_auto_orbit(&mut self, enabled: bool) {
+        self.auto_orbit = enabled;
+        self.auto_orbit_base_pitch = self.target_pitch;
+        self.auto_orbit_time = 0.0;
+        self.auto_orbit_last_update = None;
+    }
+
+    // Turntable rotation around the target. Called once per frame before update_orientation
+    pub fn update_auto_orbit(&mut self) {
+        if !self.auto_orbit {
+            return;
+        }
+
+        let now = Instant::now();
+        let dt = self
+            .auto_orbit_last_update
+            .map_or(0.0, |t| (now - t).as_secs_f64());
+        self.auto_orbit_last_update = Some(now);
+        self.auto_orbit_time += dt;
+
+        self.set_yaw(self.target_yaw + self.auto_orbit_speed * dt);
+
+        if self.auto_orbit_pitch_amplitude != 0.0 {
+            // Bob up and down once every two turns
+            let phase = self.auto_orbit_time * self.auto_orbit_speed / 2.0;
+            self.set_pitch(
+                self.auto_orbit_base_pitch + self.auto_orbit_pitch_amplitude * phase.sin(),
+            );
+        }
     }
 
     pub fn get_full_transformation(&self) -> DAffine3 {
         let translation = DAffine3::from_translation(-self.target);
+        let alignment = self.get_alignment();
 
         let rot_y = DAffine3::from_rotation_y(-self.yaw);
         let rot_x = DAffine3::from_rotation_x(-self.pitch);
 
         let scale = DAffine3::from_scale(DVec3::ONE / self.distance);
 
-        scale * rot_x * rot_y * translation
+        scale * rot_x * rot_y * alignment * translation
     }
 
     pub fn zoom(&mut self, amount: i32) {
         match amount {
-            0.. => self.distance /= 1.1,
-            _ => self.distance *= 1.1,
+            0.. => self.set_distance(self.distance / 1.1),
+            _ => self.set_distance(self.distance * 1.1),
         };
     }
 
+    // Keeps distance in a range where the projection math stays well behaved
+    pub fn set_distance(&mut self, distance: f64) {
+        if distance.is_nan() {
+            return;
+        }
+
+        self.distance = distance.clamp(MIN_DISTANCE, MAX_DISTANCE);
+    }
+
     pub fn get_position(&self) -> DVec3 {
         let scale = DAffine3::from_scale(DVec3::ONE * self.distance);
         let rot_y = DAffine3::from_rotation_y(self.yaw);
         let rot_x = DAffine3::from_rotation_x(self.pitch);
 
-        let direction_vec = (scale * rot_y * rot_x).transform_point3(DVec3::new(0.0, 0.0, 1.0));
+        let alignment = self.get_alignment().inverse();
+
+        let direction_vec =
+            (alignment * scale * rot_y * rot_x).transform_point3(DVec3::new(0.0, 0.0, 1.0));
 
         self.target + direction_vec
     }
@@ -72,16 +168,17 @@ impl Camera {
     pub fn start_animation(&mut self, start: DVec3, distance: f64) {
         self.animation_start = Some(start);
         self.animation_start_distance = distance;
+        self.animation_destination = None;
 
         let progress_c = self.animation_progress.clone();
-        *progress_c.lock().unwrap() = 0;
+        *progress_c.lock_recover() = 0;
 
         thread::spawn(move || {
             let start = Instant::now();
             let target_time = 1.5;
             loop {
                 {
-                    let mut progress = progress_c.lock().unwrap();
+                    let mut progress = progress_c.lock_recover();
                     let duration = (Instant::now() - start).as_secs_f64();
 
                     if duration >= target_time {
@@ -96,21 +193,36 @@ impl Camera {
         });
     }
 
+    // Animates from the current view to a fixed point, e.g. when resetting the view
+    pub fn start_free_animation(&mut self, destination: DVec3, distance: f64) {
+        self.start_animation(self.target, self.distance);
+        self.animation_destination =
+            Some((destination, distance.clamp(MIN_DISTANCE, MAX_DISTANCE)));
+    }
+
     pub fn get_animation_position(&self, target: DVec3, radius: f64) -> Option<(DVec3, f64)> {
         let radius_multiplier = 100.0;
+        self.interpolate_animation(target, radius * radius_multiplier)
+    }
+
+    pub fn get_free_animation_position(&self) -> Option<(DVec3, f64)> {
+        let (destination, distance) = self.animation_destination?;
+        self.interpolate_animation(destination, distance)
+    }
+
+    pub fn interpolate_animation(&self, target: DVec3, end_distance: f64) -> Option<(DVec3, f64)> {
         match self.animation_start {
             Some(start) => {
-                let progress = self.animation_progress.lock().unwrap();
+                let progress = self.animation_progress.lock_recover();
                 if *progress >= 1000 {
-                    return Some((target, radius * radius_multiplier));
+                    return Some((target, end_distance));
                 }
 
                 // let eased_progress = (*progress as f64 / 1000.0 * 2.0 * PI - PI).tanh() * 0.5 + 0.5;
                 let eased_progress = Camera::get_camera_easing(*progress);
 
                 let difference = target - start;
-                let difference_distance =
-                    radius * radius_multiplier - self.animation_start_distance;
+                let difference_distance = end_distance - self.animation_start_distance;
                 Some((
                     difference * eased_progress + start,
                     difference_distance * eased_progress + self.animation_start_distance,
@@ -138,10 +250,54 @@ impl Default for Camera {
             distance: 2.0 * AU,
             yaw: 0.0,
             pitch: 0.0,
+            target_yaw: 0.0,
+            target_pitch: 0.0,
+            smoothing: 0.0,
+            follow_velocity: false,
+            align_to_angular_momentum: false,
+            auto_orbit: false,
+            auto_orbit_speed: 2.0 * PI / 60.0,
+            auto_orbit_pitch_amplitude: PI / 18.0,
+            auto_orbit_base_pitch: 0.0,
+            auto_orbit_time: 0.0,
+            auto_orbit_last_update: None,
+            up_axis: DVec3::Y,
             fov: 80.0,
             animation_start: None,
+            animation_destination: None,
             animation_progress: Arc::new(Mutex::new(0)),
             animation_start_distance: 0.0,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_zoom_stays_finite_and_recovers() {
+        let mut camera = Camera::default();
+
+        for _ in 0..10000 {
+            camera.zoom(1);
+        }
+        assert_eq!(camera.distance, MIN_DISTANCE);
+        assert!(camera.get_position().is_finite());
+        assert!(camera.get_full_transformation().is_finite());
+
+        for _ in 0..10000 {
+            camera.zoom(-1);
+        }
+        assert_eq!(camera.distance, MAX_DISTANCE);
+        assert!(camera.get_position().is_finite());
+        assert!(camera.get_full_transformation().is_finite());
+
+        // Zooming back in works right away instead of first unwinding the extra steps
+        camera.zoom(1);
+        assert!(camera.distance < MAX_DISTANCE);
+
+        camera.set_distance(f64::NAN);
+        assert!(camera.distance.is_finite());
+    }
+}
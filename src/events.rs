@@ -1,8 +1,134 @@
-use std::{sync::atomic::Ordering, time::Instant};
+use std::{collections::HashMap, sync::atomic::Ordering, time::Instant};
 
+use glam::DVec3;
 use rand::SeedableRng;
+use uuid::Uuid;
+use winapi::um::winuser::{GetKeyState, VK_SHIFT};
 
-use crate::{astronomy::AstronomicalObject, input, integration::IntegrationMethod, DrawingApp};
+use crate::{
+    astronomy::{AstronomicalObject, AU},
+    camera::Camera,
+    engine::{BodyTrigger, Boundary, Engine},
+    export::{self, ElementLog},
+    input,
+    ui::MAX_DISPLAY_PRECISION,
+    lock::LockRecover,
+    integration::{ExternalField, IntegrationMethod},
+    DrawingApp,
+};
+
+const BOUNDARY_HALF_SIZE: f64 = 50.0 * AU;
+const COLD_COLLAPSE_BODIES: usize = 500;
+const MAX_LIMITING_MAGNITUDE: f64 = 30.0; // Going past this turns the filter off
+const ELEMENT_LOG_PATH: &str = "orbital_elements.csv";
+const ELEMENT_LOG_INTERVAL: f64 = 86400.0;
+const HIERARCHY_PATH: &str = "hierarchy.dot";
+const FIT_ALL_MARGIN: f64 = 1.2;
+const SPAWN_ATTEMPTS: usize = 5;
+const WATCH_DISTANCE: f64 = 100.0 * AU;
+const EXTERNAL_FIELD_STRENGTH: f64 = 1.0E-5; // m/s^2
+const EXTERNAL_FIELD_PERIOD: f64 = 365.25 * 86400.0;
+const TIDAL_FREQUENCY: f64 = 2.0 * std::f64::consts::PI / (10.0 * 365.25 * 86400.0);
+const MIN_SOFTENING: f64 = 1.0E5; // Decreasing below this turns softening off
+const MAX_SOFTENING: f64 = 1.0 * AU;
+const RANDOM_CLOUD_BODIES: usize = 2000;
+const BARNES_HUT_THETA: f64 = 0.5;
+const TIME_STEP_RANGE: (f64, f64) = (1.0E-6, 1.0E6); // Seconds
+const TARGET_SPEED_RANGE: (f64, f64) = (1.0, 1.0E12); // Simulated seconds per second
+
+pub fn apply_speed_input(app: &DrawingApp) {
+    let text = app.speed_input.text();
+    app.speed_input.set_text("");
+    app.window.set_focus(); // Give keyboard shortcuts back to the window
+
+    let value = match text.trim().parse::<f64>() {
+        Ok(v) if v.is_finite() && v > 0.0 => v,
+        _ => {
+            println!("Invalid value: {}", text);
+            return;
+        }
+    };
+
+    let mut params = app.engine.params.lock_recover();
+    if params.use_target_speed {
+        params.target_speed = value.clamp(TARGET_SPEED_RANGE.0, TARGET_SPEED_RANGE.1);
+    } else {
+        params.time_step = value.clamp(TIME_STEP_RANGE.0, TIME_STEP_RANGE.1);
+    }
+
+    *app.next_status_update.borrow_mut() = Instant::now();
+}
+
+// Replaces the bodies of the previous random cloud with new ones orbiting the first body. Other bodies and the
+// elapsed time are kept. Only allowed while the simulation is stopped
+pub fn load_random_cloud(app: &DrawingApp, seed: u64) -> bool {
+    let mut random_cloud = app.random_cloud.borrow_mut();
+
+    let uuids = app.engine.edit_objects(|objects| {
+        let center = objects.iter().find(|x| !random_cloud.contains(&x.uuid))?.clone();
+        objects.retain(|x| !random_cloud.contains(&x.uuid));
+
+        let cloud = AstronomicalObject::random_cloud(seed, RANDOM_CLOUD_BODIES, &center);
+        let uuids: Vec<Uuid> = cloud.iter().map(|x| x.uuid).collect();
+        objects.extend(cloud);
+
+        Some(uuids)
+    });
+    let uuids = match uuids.flatten() {
+        Some(u) => u,
+        None => return false,
+    };
+
+    let mut current_target = app.current_target.borrow_mut();
+    if current_target.is_some_and(|x| random_cloud.contains(&x)) {
+        *current_target = None;
+    }
+    app.focus_group.borrow_mut().retain(|x| !random_cloud.contains(x));
+
+    *random_cloud = uuids;
+    *app.cloud_seed.borrow_mut() = Some(seed);
+    *app.next_status_update.borrow_mut() = Instant::now();
+
+    true
+}
+
+fn clear_perihelion_marks(app: &DrawingApp) {
+    app.perihelia.borrow_mut().clear();
+}
+
+fn update_speed_input_placeholder(app: &DrawingApp, use_target_speed: bool) {
+    app.speed_input.set_placeholder_text(Some(match use_target_speed {
+        true => "Target speed (s/s)",
+        false => "Time step (s)",
+    }));
+}
+
+// Body and everything orbiting it, directly or through other bodies
+fn get_subsystem(objects: &[AstronomicalObject], uuid: Uuid) -> Vec<Uuid> {
+    let parents: HashMap<Uuid, Uuid> = objects
+        .iter()
+        .filter_map(|x| Engine::find_orbital_parent(x, objects).map(|p| (x.uuid, p.uuid)))
+        .collect();
+
+    objects
+        .iter()
+        .map(|x| x.uuid)
+        .filter(|x| {
+            // Parent cycles end the walk once every body could have been visited
+            let mut current = *x;
+            for _ in 0..=objects.len() {
+                if current == uuid {
+                    return true;
+                }
+                match parents.get(&current) {
+                    Some(parent) => current = *parent,
+                    None => return false,
+                }
+            }
+            false
+        })
+        .collect()
+}
 
 pub fn handle_event(app: &DrawingApp, evt: nwg::Event, evt_data: &nwg::EventData) {
     use nwg::Event as E;
@@ -17,6 +143,8 @@ pub fn handle_event(app: &DrawingApp, evt: nwg::Event, evt_data: &nwg::EventData
             input::input_listener(app.is_dragging.clone(), app.camera.clone());
         }
         E::OnMousePress(M::MousePressLeftDown) => {
+            app.window.set_focus();
+
             let (w_x, w_y) = app.window.position();
             let (m_x, m_y) = winput::Mouse::position().unwrap();
 
@@ -31,7 +159,39 @@ pub fn handle_event(app: &DrawingApp, evt: nwg::Event, evt_data: &nwg::EventData
                 let r = target.radius as i64;
 
                 if (tx - x as i64).pow(2) + (ty - y as i64).pow(2) <= r.pow(2) {
-                    if app.camera.lock().unwrap().animation_start.is_some() {
+                    // Shift + click adds or removes the body from the followed group
+                    if unsafe { GetKeyState(VK_SHIFT) } < 0 {
+                        let mut focus_group = app.focus_group.borrow_mut();
+                        match focus_group.iter().position(|x| *x == target.uuid) {
+                            Some(index) => {
+                                focus_group.remove(index);
+                            }
+                            None => focus_group.push(target.uuid),
+                        }
+                        drop(focus_group);
+                        *app.next_status_update.borrow_mut() = Instant::now();
+
+                        let mut camera = app.camera.lock_recover();
+                        let start = camera.target;
+                        let start_dis = camera.distance;
+                        camera.start_animation(start, start_dis);
+                        break;
+                    }
+
+                    if let Some(measurement) = app.measurement.borrow_mut().as_mut() {
+                        // Start a new pair once both bodies have been picked
+                        if measurement.len() == 2 {
+                            measurement.clear();
+                        }
+                        if !measurement.contains(&target.uuid) {
+                            measurement.push(target.uuid);
+                        }
+
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                        break;
+                    }
+
+                    if app.camera.lock_recover().animation_start.is_some() {
                         break;
                     }
 
@@ -44,11 +204,12 @@ pub fn handle_event(app: &DrawingApp, evt: nwg::Event, evt_data: &nwg::EventData
                     }
 
                     *current_target = Some(target.uuid);
+                    app.focus_group.borrow_mut().clear();
                     *app.next_status_update.borrow_mut() = Instant::now();
 
                     drop(current_target); // Important! get_paint_objects acquires both of the locks and can cause deadlocks if this is not dropped
 
-                    let mut camera = app.camera.lock().unwrap();
+                    let mut camera = app.camera.lock_recover();
                     let start = camera.target;
                     let start_dis = camera.distance;
 
@@ -68,7 +229,7 @@ pub fn handle_event(app: &DrawingApp, evt: nwg::Event, evt_data: &nwg::EventData
                 match key {
                     // Numpad +
                     107 => {
-                        let mut params = app.engine.params.lock().unwrap();
+                        let mut params = app.engine.params.lock_recover();
                         if params.use_target_speed {
                             params.target_speed *= 1.2;
                         } else {
@@ -79,7 +240,7 @@ pub fn handle_event(app: &DrawingApp, evt: nwg::Event, evt_data: &nwg::EventData
                     }
                     // Numpad -
                     109 => {
-                        let mut params = app.engine.params.lock().unwrap();
+                        let mut params = app.engine.params.lock_recover();
                         if params.use_target_speed {
                             params.target_speed /= 1.2;
                         } else {
@@ -88,9 +249,28 @@ pub fn handle_event(app: &DrawingApp, evt: nwg::Event, evt_data: &nwg::EventData
 
                         *app.next_status_update.borrow_mut() = Instant::now();
                     }
+                    // Numpad *
+                    106 => {
+                        let mut params = app.engine.params.lock_recover();
+                        params.softening = if params.softening == 0.0 {
+                            MIN_SOFTENING
+                        } else {
+                            (params.softening * 2.0).min(MAX_SOFTENING)
+                        };
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // Numpad /
+                    111 => {
+                        let mut params = app.engine.params.lock_recover();
+                        params.softening /= 2.0;
+                        if params.softening < MIN_SOFTENING {
+                            params.softening = 0.0;
+                        }
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
                     // Space
                     32 => {
-                        if app.engine.params.lock().unwrap().is_running {
+                        if app.engine.params.lock_recover().is_running {
                             app.engine.stop();
                         } else {
                             app.engine.start_mt();
@@ -99,28 +279,48 @@ pub fn handle_event(app: &DrawingApp, evt: nwg::Event, evt_data: &nwg::EventData
                     // Number keys
                     49..=57 => {
                         let threads = key - 48;
-                        let mut params = app.engine.params.lock().unwrap();
+                        let mut params = app.engine.params.lock_recover();
 
                         params.num_threads = threads as usize;
                         *app.next_status_update.borrow_mut() = Instant::now();
                     }
-                    // q
+                    // q, shift + q lowers the work chunks per thread
                     81 => {
-                        let mut params = app.engine.params.lock().unwrap();
+                        let mut params = app.engine.params.lock_recover();
 
-                        params.num_threads = (params.num_threads - 1).max(1);
+                        if unsafe { GetKeyState(VK_SHIFT) } < 0 {
+                            params.chunks_per_thread = (params.chunks_per_thread - 1).max(1);
+                        } else {
+                            params.num_threads = (params.num_threads - 1).max(1);
+                        }
                         *app.next_status_update.borrow_mut() = Instant::now();
                     }
-                    // w
+                    // w, shift + w raises the work chunks per thread
                     87 => {
-                        let mut params = app.engine.params.lock().unwrap();
+                        let mut params = app.engine.params.lock_recover();
 
-                        params.num_threads += 1;
+                        if unsafe { GetKeyState(VK_SHIFT) } < 0 {
+                            params.chunks_per_thread += 1;
+                        } else {
+                            params.num_threads += 1;
+                        }
                         *app.next_status_update.borrow_mut() = Instant::now();
                     }
-                    // m
+                    // m cycles the method, shift + m the accuracy presets
                     77 => {
-                        let mut params = app.engine.params.lock().unwrap();
+                        let mut params = app.engine.params.lock_recover();
+                        if unsafe { GetKeyState(VK_SHIFT) } < 0 {
+                            params.accuracy = params.accuracy.next();
+                            if let Some(method) = params.accuracy.method() {
+                                params.method = method;
+                            }
+                            if let Some(softening) = params.accuracy.softening() {
+                                params.softening = softening;
+                            }
+                            *app.next_status_update.borrow_mut() = Instant::now();
+                            return;
+                        }
+
                         params.method = match params.method {
                             IntegrationMethod::Symplectic(n) => {
                                 if n == 4 {
@@ -129,31 +329,439 @@ pub fn handle_event(app: &DrawingApp, evt: nwg::Event, evt_data: &nwg::EventData
                                     IntegrationMethod::Symplectic(n + 1)
                                 }
                             }
-                            IntegrationMethod::RK4 => IntegrationMethod::Symplectic(1),
+                            IntegrationMethod::RK4 => IntegrationMethod::Kepler,
+                            IntegrationMethod::Kepler => IntegrationMethod::BarnesHut {
+                                theta: BARNES_HUT_THETA,
+                                order: 4,
+                            },
+                            IntegrationMethod::BarnesHut { .. } => IntegrationMethod::Symplectic(1),
                         };
                         *app.next_status_update.borrow_mut() = Instant::now();
                     }
                     // t
                     82 => {
-                        let params = app.engine.params.lock().unwrap();
-                        if params.is_running {
-                            return;
-                        }
-
-                        let mut objects = app.engine.objects.lock().unwrap();
                         let mut rng = rand::rngs::StdRng::from_entropy();
-                        let new_object = AstronomicalObject::place_on_orbit(
+                        let objects = app.engine.snapshot();
+                        let new_object = AstronomicalObject::place_on_orbit_clear(
                             AstronomicalObject::get_random_planet(&mut rng),
                             &objects[0],
+                            &objects,
+                            SPAWN_ATTEMPTS,
                             &mut rng
                         );
-                        objects.push(new_object);
+
+                        match new_object {
+                            Some(object) => app.engine.add_object(object),
+                            None => println!("Could not find a free spot for the new body after {} attempts", SPAWN_ATTEMPTS),
+                        }
                         *app.next_status_update.borrow_mut() = Instant::now();
                     }
                     // n
                     84 => {
-                        let mut params = app.engine.params.lock().unwrap();
+                        let mut params = app.engine.params.lock_recover();
                         params.use_target_speed = !params.use_target_speed;
+                        update_speed_input_placeholder(app, params.use_target_speed);
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // b
+                    66 => {
+                        if !app.engine.normalize_to_barycenter() {
+                            println!("Stop the simulation before moving to the barycentric frame");
+                        }
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // i
+                    73 => {
+                        let mut camera = app.camera.lock_recover();
+                        camera.smoothing = match camera.smoothing {
+                            s if s < 0.5 => 0.5,
+                            s if s < 0.8 => 0.8,
+                            s if s < 0.9 => 0.9,
+                            _ => 0.0,
+                        };
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // u
+                    85 => {
+                        let mut render_options = app.render_options.borrow_mut();
+                        render_options.bound_filter = render_options.bound_filter.next();
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // d, shift + d toggles deterministic collision order between threads
+                    68 => {
+                        if unsafe { GetKeyState(VK_SHIFT) } < 0 {
+                            let mut params = app.engine.params.lock_recover();
+                            params.ordered_collisions = !params.ordered_collisions;
+                        } else {
+                            let mut render_options = app.render_options.borrow_mut();
+                            render_options.doppler = !render_options.doppler;
+                        }
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // x
+                    88 => {
+                        let mut params = app.engine.params.lock_recover();
+                        params.boundary = match params.boundary {
+                            Boundary::Off => Boundary::Periodic(BOUNDARY_HALF_SIZE),
+                            Boundary::Periodic(_) => Boundary::Reflect(BOUNDARY_HALF_SIZE),
+                            Boundary::Reflect(_) => Boundary::Off,
+                        };
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // v, shift + v toggles the turntable auto-orbit
+                    86 => {
+                        let mut camera = app.camera.lock_recover();
+                        if unsafe { GetKeyState(VK_SHIFT) } < 0 {
+                            let auto_orbit = !camera.is_auto_orbiting();
+                            camera.set_auto_orbit(auto_orbit);
+                        } else {
+                            camera.follow_velocity = !camera.follow_velocity;
+                        }
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // l
+                    76 => {
+                        let mut render_options = app.render_options.borrow_mut();
+                        render_options.sun_lighting = !render_options.sun_lighting;
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // g
+                    71 => {
+                        let mut render_options = app.render_options.borrow_mut();
+                        render_options.log_radial_scale = !render_options.log_radial_scale;
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // k
+                    75 => {
+                        if app.engine.params.lock_recover().is_running {
+                            return;
+                        }
+
+                        let target = match *app.current_target.borrow() {
+                            Some(t) => t,
+                            None => return,
+                        };
+
+                        app.engine.edit_objects(|objects| {
+                            let index = match objects.iter().position(|x| x.uuid == target) {
+                                Some(i) => i,
+                                None => return,
+                            };
+
+                            // Toggle between integrated and analytic Kepler motion around the orbital parent
+                            objects[index].scenery_parent = match objects[index].scenery_parent {
+                                Some(_) => None,
+                                None => Engine::find_orbital_parent(&objects[index], objects)
+                                    .or_else(|| objects.iter()
+                                        .filter(|x| x.uuid != target)
+                                        .max_by(|a, b| a.mass.total_cmp(&b.mass)))
+                                    .map(|x| x.uuid),
+                            };
+                        });
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // p
+                    80 => {
+                        let mut measurement = app.measurement.borrow_mut();
+                        *measurement = match *measurement {
+                            Some(_) => None,
+                            None => Some(Vec::new()),
+                        };
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // [
+                    219 => {
+                        let mut render_options = app.render_options.borrow_mut();
+                        render_options.limiting_magnitude = Some(
+                            render_options
+                                .limiting_magnitude
+                                .map_or(MAX_LIMITING_MAGNITUDE, |m| m - 1.0),
+                        );
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // ]
+                    221 => {
+                        let mut render_options = app.render_options.borrow_mut();
+                        render_options.limiting_magnitude = render_options
+                            .limiting_magnitude
+                            .map(|m| m + 1.0)
+                            .filter(|m| *m <= MAX_LIMITING_MAGNITUDE);
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // c, shift + c marks the selected body as a comet
+                    67 => {
+                        if unsafe { GetKeyState(VK_SHIFT) } >= 0 {
+                            let mut render_options = app.render_options.borrow_mut();
+                            render_options.comet_tails = !render_options.comet_tails;
+                            return;
+                        }
+
+                        if app.engine.params.lock_recover().is_running {
+                            return;
+                        }
+
+                        if let Some(target) = *app.current_target.borrow() {
+                            app.engine.edit_objects(|objects| {
+                                if let Some(object) = objects.iter_mut().find(|x| x.uuid == target) {
+                                    object.is_comet = !object.is_comet;
+                                }
+                            });
+                        }
+                    }
+                    // o
+                    79 => {
+                        let seed = rand::random::<u64>();
+                        if app
+                            .engine
+                            .load_system(AstronomicalObject::cold_collapse(seed, COLD_COLLAPSE_BODIES))
+                        {
+                            println!("Loaded cold collapse with seed {}", seed);
+                            app.random_cloud.borrow_mut().clear();
+                            *app.cloud_seed.borrow_mut() = None;
+                            clear_perihelion_marks(app);
+                            *app.current_target.borrow_mut() = None;
+                        } else {
+                            println!("Stop the simulation before loading a new system");
+                        }
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // a, shift + a toggles perihelion marks
+                    65 => {
+                        let mut render_options = app.render_options.borrow_mut();
+                        if unsafe { GetKeyState(VK_SHIFT) } < 0 {
+                            render_options.perihelion_marks = !render_options.perihelion_marks;
+                            clear_perihelion_marks(app);
+                        } else {
+                            render_options.apsis_markers = !render_options.apsis_markers;
+                        }
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // y
+                    89 => {
+                        let mut camera = app.camera.lock_recover();
+                        camera.align_to_angular_momentum = !camera.align_to_angular_momentum;
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // e
+                    69 => {
+                        let mut element_log = app.engine.element_log.lock_recover();
+
+                        if let Some(mut log) = element_log.take() {
+                            if let Err(e) = log.flush() {
+                                eprintln!("Failed to flush orbital element log: {}", e);
+                            }
+                            println!("Stopped logging orbital elements");
+                        } else if let Some(target) = *app.current_target.borrow() {
+                            match ElementLog::new(ELEMENT_LOG_PATH, vec![target], ELEMENT_LOG_INTERVAL) {
+                                Ok(log) => {
+                                    *element_log = Some(log);
+                                    println!("Logging orbital elements to {}", ELEMENT_LOG_PATH);
+                                }
+                                Err(e) => eprintln!("Could not create {}: {}", ELEMENT_LOG_PATH, e),
+                            }
+                        }
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // h resets the view, shift + h frames all bodies
+                    72 => {
+                        *app.current_target.borrow_mut() = None;
+                        app.focus_group.borrow_mut().clear();
+
+                        let (target, distance) = if unsafe { GetKeyState(VK_SHIFT) } >= 0 {
+                            let default = Camera::default();
+                            (default.target, default.distance)
+                        } else {
+                            let (center, radius) =
+                                Engine::get_bounding_sphere(&app.engine.snapshot());
+                            let fov = app.camera.lock_recover().fov;
+                            (center, radius * FIT_ALL_MARGIN / (fov / 2.0).to_radians().sin())
+                        };
+
+                        let mut camera = app.camera.lock_recover();
+                        camera.set_yaw(0.0);
+                        camera.set_pitch(0.0);
+                        camera.start_free_animation(target, distance);
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // j toggles watching the selected body. Escaping stops the simulation
+                    74 => {
+                        let target = match *app.current_target.borrow() {
+                            Some(t) => t,
+                            None => return,
+                        };
+
+                        if app.engine.unwatch_body(target) {
+                            println!("Stopped watching selected body");
+                            return;
+                        }
+
+                        for trigger in [BodyTrigger::Collision, BodyTrigger::Escape, BodyTrigger::Distance(WATCH_DISTANCE)] {
+                            app.engine.watch_body(target, trigger, |context| {
+                                println!(
+                                    "{:.0} s: {} triggered {:?}",
+                                    context.time, context.body.name, context.trigger
+                                );
+                                context.stop = context.trigger == BodyTrigger::Escape;
+                            });
+                        }
+                        println!("Watching selected body");
+                    }
+                    // n
+                    78 => {
+                        let mut render_options = app.render_options.borrow_mut();
+                        render_options.roche_limits = render_options.roche_limits.next();
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // z
+                    90 => {
+                        let mut params = app.engine.params.lock_recover();
+                        params.blend_merge_colors = !params.blend_merge_colors;
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // f
+                    70 => {
+                        let mut render_options = app.render_options.borrow_mut();
+                        render_options.smooth_overlays = !render_options.smooth_overlays;
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // s
+                    83 => {
+                        let mut params = app.engine.params.lock_recover();
+                        params.external_field = match params.external_field {
+                            ExternalField::Off => ExternalField::Uniform(DVec3::X * EXTERNAL_FIELD_STRENGTH),
+                            ExternalField::Uniform(_) => ExternalField::Oscillating {
+                                amplitude: DVec3::X * EXTERNAL_FIELD_STRENGTH,
+                                period: EXTERNAL_FIELD_PERIOD,
+                            },
+                            ExternalField::Oscillating { .. } => ExternalField::Tidal(TIDAL_FREQUENCY),
+                            ExternalField::Tidal(_) => ExternalField::Off,
+                        };
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // Comma, shift + comma cycles the depth cueing curve
+                    188 => {
+                        let mut render_options = app.render_options.borrow_mut();
+                        if unsafe { GetKeyState(VK_SHIFT) } < 0 {
+                            render_options.depth_cue_gamma = match render_options.depth_cue_gamma {
+                                g if g < 1.0 => 1.0,
+                                g if g < 2.0 => 2.0,
+                                _ => 0.5,
+                            };
+                        } else {
+                            render_options.depth_cueing = !render_options.depth_cueing;
+                        }
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // F3
+                    114 => {
+                        let mut params = app.engine.params.lock_recover();
+                        params.scenery_gravity = !params.scenery_gravity;
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // F5
+                    116 => {
+                        let state = app.engine.checkpoint();
+                        println!("Checkpoint saved at {:.0} s", state.params.time_elapsed);
+                        *app.checkpoint.borrow_mut() = Some(state);
+                    }
+                    // F9
+                    120 => {
+                        let state = match app.checkpoint.borrow().clone() {
+                            Some(s) => s,
+                            None => return,
+                        };
+
+                        if app.engine.restore(state) {
+                            println!("Checkpoint restored");
+                            clear_perihelion_marks(app);
+                        } else {
+                            println!("Stop the simulation before restoring a checkpoint");
+                        }
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // Period
+                    190 => {
+                        let mut render_options = app.render_options.borrow_mut();
+                        render_options.render_shape = render_options.render_shape.next();
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // Slash
+                    191 => {
+                        let mut params = app.engine.params.lock_recover();
+                        params.watchdog_policy = params.watchdog_policy.next();
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // Semicolon
+                    186 => {
+                        let mut params = app.engine.params.lock_recover();
+                        params.collision_slowdown = !params.collision_slowdown;
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // 0
+                    48 => {
+                        let mut render_options = app.render_options.borrow_mut();
+                        render_options.display_precision =
+                            render_options.display_precision % MAX_DISPLAY_PRECISION + 1;
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // Equals
+                    187 => {
+                        let mut render_options = app.render_options.borrow_mut();
+                        render_options.target_reticle = !render_options.target_reticle;
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // Minus
+                    189 => {
+                        let mut render_options = app.render_options.borrow_mut();
+                        render_options.orbit_planes = render_options.orbit_planes.next();
+                        *app.next_status_update.borrow_mut() = Instant::now();
+                    }
+                    // Backslash
+                    220 => {
+                        let seed = rand::random::<u64>();
+                        if load_random_cloud(app, seed) {
+                            println!("Rerolled random cloud with seed {}", seed);
+                            clear_perihelion_marks(app);
+                        } else {
+                            println!("Stop the simulation before rerolling the random cloud");
+                        }
+                    }
+                    // Apostrophe toggles the hierarchy list, shift + apostrophe exports it
+                    222 => {
+                        if unsafe { GetKeyState(VK_SHIFT) } < 0 {
+                            let objects = app.engine.snapshot();
+                            let hierarchy = Engine::get_hierarchy(&objects);
+                            match export::write_hierarchy_dot(HIERARCHY_PATH, &hierarchy, &objects) {
+                                Ok(()) => println!("Hierarchy exported to {}", HIERARCHY_PATH),
+                                Err(e) => eprintln!("Could not write {}: {}", HIERARCHY_PATH, e),
+                            }
+                        } else {
+                            let mut render_options = app.render_options.borrow_mut();
+                            render_options.hierarchy = !render_options.hierarchy;
+                            *app.next_status_update.borrow_mut() = Instant::now();
+                        }
+                    }
+                    // F2, cycles the symplectic order of the selected body and everything orbiting it
+                    113 => {
+                        let target = match *app.current_target.borrow() {
+                            Some(t) => t,
+                            None => return,
+                        };
+                        let subsystem = get_subsystem(&app.engine.snapshot(), target);
+
+                        let mut params = app.engine.params.lock_recover();
+                        let order = match params.body_order(target) {
+                            None => Some(1),
+                            Some(k) if k < 4 => Some(k + 1),
+                            Some(_) => None,
+                        };
+
+                        for uuid in subsystem {
+                            params.set_body_order(uuid, order);
+                        }
+                        if order.is_some() && !matches!(params.method, IntegrationMethod::Symplectic(_)) {
+                            println!("Body orders are kept, but only used with direct symplectic integration");
+                        }
                         *app.next_status_update.borrow_mut() = Instant::now();
                     }
                     key => {
@@ -0,0 +1,139 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use uuid::Uuid;
+
+use crate::{
+    astronomy::{AstronomicalObject, AU},
+    engine::{Engine, HierarchyNode},
+};
+
+// Writes orbital elements of the tracked bodies as CSV rows every `interval` simulated seconds
+pub struct ElementLog {
+    writer: BufWriter<File>,
+    tracked: Vec<Uuid>,
+    interval: f64,
+    next_time: Option<f64>,
+}
+
+impl ElementLog {
+    pub fn new(path: &str, tracked: Vec<Uuid>, interval: f64) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "time,name,uuid,parent,semi_major_axis,eccentricity,inclination,longitude_of_ascending_node,argument_of_periapsis,true_anomaly"
+        )?;
+
+        Ok(ElementLog {
+            writer,
+            tracked,
+            interval,
+            next_time: None,
+        })
+    }
+
+    pub fn record(&mut self, time: f64, objects: &[AstronomicalObject]) -> io::Result<()> {
+        if self.next_time.is_some_and(|t| time < t) {
+            return Ok(());
+        }
+        self.next_time = Some(time + self.interval);
+
+        for uuid in self.tracked.iter() {
+            let body = match objects.iter().find(|x| x.uuid == *uuid) {
+                Some(b) => b,
+                None => continue, // Absorbed in a collision
+            };
+
+            let parent = match Engine::find_orbital_parent(body, objects).or_else(|| {
+                objects
+                    .iter()
+                    .filter(|x| x.uuid != body.uuid)
+                    .max_by(|a, b| a.mass.total_cmp(&b.mass))
+            }) {
+                Some(p) => p,
+                None => continue,
+            };
+
+            let elements = body.get_orbital_elements(parent);
+            writeln!(
+                self.writer,
+                "{},{},{},{},{:e},{},{},{},{},{}",
+                time,
+                body.name,
+                body.uuid,
+                parent.name,
+                elements.semi_major_axis,
+                elements.eccentricity,
+                elements.inclination.to_degrees(),
+                elements.longitude_of_ascending_node.to_degrees(),
+                elements.argument_of_periapsis.to_degrees(),
+                elements.true_anomaly.to_degrees()
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// Writes the orbital hierarchy as a Graphviz digraph. Edges point from parents to the bodies orbiting them
+pub fn write_hierarchy_dot(
+    path: &str,
+    hierarchy: &[HierarchyNode],
+    objects: &[AstronomicalObject],
+) -> io::Result<()> {
+    fn write_node(
+        writer: &mut impl Write,
+        node: &HierarchyNode,
+        objects: &[AstronomicalObject],
+    ) -> io::Result<()> {
+        let body = match objects.iter().find(|x| x.uuid == node.uuid) {
+            Some(b) => b,
+            None => return Ok(()),
+        };
+
+        let [r, g, b] = body.color;
+        writeln!(
+            writer,
+            "    \"{}\" [label=\"{}\", mass=\"{:e}\", radius=\"{:e}\", fillcolor=\"#{:02x}{:02x}{:02x}\"];",
+            node.uuid,
+            node.name.replace('"', "\\\""),
+            body.mass,
+            body.radius,
+            r,
+            g,
+            b
+        )?;
+
+        for child in node.children.iter() {
+            if let Some(child_body) = objects.iter().find(|x| x.uuid == child.uuid) {
+                writeln!(
+                    writer,
+                    "    \"{}\" -> \"{}\" [label=\"{:.4} AU\"];",
+                    node.uuid,
+                    child.uuid,
+                    body.position.distance(child_body.position) / AU
+                )?;
+            }
+            write_node(writer, child, objects)?;
+        }
+
+        Ok(())
+    }
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "digraph hierarchy {{")?;
+    writeln!(writer, "    node [style=filled];")?;
+
+    for root in hierarchy.iter() {
+        write_node(&mut writer, root, objects)?;
+    }
+
+    writeln!(writer, "}}")?;
+    writer.flush()
+}
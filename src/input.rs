@@ -4,6 +4,7 @@ use std::thread::{self, JoinHandle};
 use std::time::Duration;
 
 use crate::camera::Camera;
+use crate::lock::LockRecover;
 
 pub fn input_listener(mouse_down: Arc<AtomicBool>, camera: Arc<Mutex<Camera>>) -> JoinHandle<()> {
     const SENSITIVITY: f64 = 0.003;
@@ -12,7 +13,8 @@ pub fn input_listener(mouse_down: Arc<AtomicBool>, camera: Arc<Mutex<Camera>>) -
         let duration = Duration::from_millis(10);
         let (pitch_start, yaw_start);
         {
-            let cam = camera.lock().unwrap();
+            let mut cam = camera.lock_recover();
+            cam.set_auto_orbit(false); // Manual input takes over
             pitch_start = cam.get_pitch();
             yaw_start = cam.get_yaw();
         }
@@ -26,7 +28,7 @@ pub fn input_listener(mouse_down: Arc<AtomicBool>, camera: Arc<Mutex<Camera>>) -
             let pitch_add = (y_start - y) as f64 * SENSITIVITY; // Positive value should decrease pitch, so swap order of direction to make math easier. This way positive value increases pitch
             let yaw_add = (x_start - x) as f64 * SENSITIVITY; // Positive value should decrease yaw
 
-            let mut cam = camera.lock().unwrap();
+            let mut cam = camera.lock_recover();
             cam.set_pitch(pitch_start + pitch_add);
             cam.set_yaw(yaw_start + yaw_add);
         }
@@ -1,4 +1,5 @@
 use std::ops::DivAssign;
+use std::thread;
 
 use glam::DVec3;
 
@@ -6,41 +7,78 @@ use crate::astronomy::AstronomicalObject;
 
 pub const G: f64 = 6.6743E-11;
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum IntegrationMethod {
     Symplectic(u8),
     RK4,
+    Kepler, // Exact solution for a lone bound pair. Anything else is integrated with RK4
+    BarnesHut { theta: f64, order: u8 }, // Symplectic with octree approximated forces, for large systems
+}
+
+// Acceleration applied to every body on top of the mutual gravity
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExternalField {
+    Off,
+    Uniform(DVec3),
+    Oscillating { amplitude: DVec3, period: f64 },
+    Tidal(f64), // Vertical galactic tide pulling towards the XZ-plane. Value is the oscillation frequency (rad/s)
+}
+
+impl ExternalField {
+    pub fn acceleration(&self, position: DVec3, time: f64) -> DVec3 {
+        match *self {
+            ExternalField::Off => DVec3::ZERO,
+            ExternalField::Uniform(acceleration) => acceleration,
+            ExternalField::Oscillating { amplitude, period } => {
+                amplitude * (2.0 * std::f64::consts::PI * time / period).sin()
+            }
+            ExternalField::Tidal(frequency) => {
+                DVec3::new(0.0, -frequency.powi(2) * position.y, 0.0)
+            }
+        }
+    }
+}
+
+// Cube of the distance used for forces. Softening keeps close encounters from producing huge accelerations
+pub fn softened_distance_cubed(distance: f64, softening: f64) -> f64 {
+    if softening == 0.0 {
+        distance.powi(3)
+    } else {
+        (distance.powi(2) + softening.powi(2)).powf(1.5)
+    }
 }
 
 // c and d coefficients for symplectic integrator
 impl IntegrationMethod {
     pub fn get_coefficients(&self) -> Vec<(f64, f64)> {
         match &self {
-            IntegrationMethod::Symplectic(k) => match k {
-                1 => vec![(1.0, 1.0)],
-                2 => vec![(0.0, 0.5), (1.0, 0.5)],
-                3 => vec![
-                    (1.0, -1.0 / 24.0),
-                    (-2.0 / 3.0, 3.0 / 4.0),
-                    (2.0 / 3.0, 7.0 / 24.0),
-                ],
-                4 => vec![
-                    (
-                        1.0 / (4.0 - 2.0f64.powf(4.0 / 3.0)),
-                        1.0 / (2.0 - 2.0f64.powf(1.0 / 3.0)),
-                    ),
-                    (
-                        (1.0 - 2.0f64.powf(1.0 / 3.0)) / (4.0 - 2.0f64.powf(4.0 / 3.0)),
-                        -(2.0f64.powf(1.0 / 3.0) / (2.0 - 2.0f64.powf(1.0 / 3.0))),
-                    ),
-                    (
-                        (1.0 - 2.0f64.powf(1.0 / 3.0)) / (4.0 - 2.0f64.powf(4.0 / 3.0)),
-                        1.0 / (2.0 - 2.0f64.powf(1.0 / 3.0)),
-                    ),
-                    (1.0 / (4.0 - 2.0f64.powf(4.0 / 3.0)), 0.0),
-                ],
-                _ => vec![],
-            },
+            IntegrationMethod::Symplectic(k) | IntegrationMethod::BarnesHut { order: k, .. } => {
+                match k {
+                    1 => vec![(1.0, 1.0)],
+                    2 => vec![(0.0, 0.5), (1.0, 0.5)],
+                    3 => vec![
+                        (1.0, -1.0 / 24.0),
+                        (-2.0 / 3.0, 3.0 / 4.0),
+                        (2.0 / 3.0, 7.0 / 24.0),
+                    ],
+                    4 => vec![
+                        (
+                            1.0 / (4.0 - 2.0f64.powf(4.0 / 3.0)),
+                            1.0 / (2.0 - 2.0f64.powf(1.0 / 3.0)),
+                        ),
+                        (
+                            (1.0 - 2.0f64.powf(1.0 / 3.0)) / (4.0 - 2.0f64.powf(4.0 / 3.0)),
+                            -(2.0f64.powf(1.0 / 3.0) / (2.0 - 2.0f64.powf(1.0 / 3.0))),
+                        ),
+                        (
+                            (1.0 - 2.0f64.powf(1.0 / 3.0)) / (4.0 - 2.0f64.powf(4.0 / 3.0)),
+                            1.0 / (2.0 - 2.0f64.powf(1.0 / 3.0)),
+                        ),
+                        (1.0 / (4.0 - 2.0f64.powf(4.0 / 3.0)), 0.0),
+                    ],
+                    _ => vec![],
+                }
+            }
             _ => vec![],
         }
     }
@@ -55,7 +93,14 @@ struct IntermediateState {
 pub fn runge_kutta_4(
     local_bodies: &mut [AstronomicalObject],
     time_step: f64,
+    field: &ExternalField,
+    time: f64,
+    softening: f64,
 ) -> Option<(usize, usize)> {
+    if local_bodies.len() == 2 {
+        return runge_kutta_4_pair(local_bodies, time_step, field, time, softening);
+    }
+
     let mut dt = 0.5f64 * time_step;
     let num_bodies = local_bodies.len();
 
@@ -88,13 +133,18 @@ pub fn runge_kutta_4(
                 if state == 0 && distance <= local_bodies[i].radius + local_bodies[j].radius {
                     return Some((i, j)); // Process collisions before update results
                 }
-                let grav_modifier = G / (difference.length().powi(3));
+                let grav_modifier = G / softened_distance_cubed(distance, softening);
 
                 dv[i] += grav_modifier * local_bodies[j].mass * difference;
                 dv[j] += -grav_modifier * local_bodies[i].mass * difference;
             }
         }
 
+        let stage_time = if state == 0 { time } else { time + dt };
+        for (accel, position) in dv.iter_mut().zip(&positions) {
+            *accel += field.acceleration(*position, stage_time);
+        }
+
         s[state] = dv
             .into_iter()
             .enumerate()
@@ -127,14 +177,75 @@ pub fn runge_kutta_4(
     None
 }
 
+// Same stages as the general loop for a lone pair, without allocating the intermediate states
+fn runge_kutta_4_pair(
+    local_bodies: &mut [AstronomicalObject],
+    time_step: f64,
+    field: &ExternalField,
+    time: f64,
+    softening: f64,
+) -> Option<(usize, usize)> {
+    let (a, b) = (&local_bodies[0], &local_bodies[1]);
+    if a.position.distance(b.position) <= a.radius + b.radius {
+        return Some((0, 1));
+    }
+
+    let (mass_a, mass_b) = (a.mass, b.mass);
+    let positions = [a.position, b.position];
+    let velocities = [a.velocity, b.velocity];
+
+    let accelerations = |positions: [DVec3; 2], stage_time: f64| {
+        let difference = positions[1] - positions[0];
+        let grav_modifier = G / softened_distance_cubed(difference.length(), softening);
+        [
+            grav_modifier * mass_b * difference + field.acceleration(positions[0], stage_time),
+            -grav_modifier * mass_a * difference + field.acceleration(positions[1], stage_time),
+        ]
+    };
+    let drift = |velocity: [DVec3; 2], dt: f64| {
+        [
+            positions[0] + velocity[0] * dt,
+            positions[1] + velocity[1] * dt,
+        ]
+    };
+    let kick = |acceleration: [DVec3; 2], dt: f64| {
+        [
+            velocities[0] + acceleration[0] * dt,
+            velocities[1] + acceleration[1] * dt,
+        ]
+    };
+
+    let half_step = 0.5 * time_step;
+    let a1 = accelerations(positions, time);
+    let v2 = kick(a1, half_step);
+    let a2 = accelerations(drift(velocities, half_step), time + half_step);
+    let v3 = kick(a2, half_step);
+    let a3 = accelerations(drift(v2, half_step), time + half_step);
+    let v4 = kick(a3, time_step);
+    let a4 = accelerations(drift(v3, time_step), time + time_step);
+
+    for (i, body) in local_bodies.iter_mut().enumerate() {
+        let dxdt = (velocities[i] + (v2[i] + v3[i]) * 2.0 + v4[i]) / 6.0;
+        let dvdt = (a1[i] + (a2[i] + a3[i]) * 2.0 + a4[i]) / 6.0;
+
+        body.position += dxdt * time_step;
+        body.velocity += dvdt * time_step;
+        body.acceleration = dvdt;
+    }
+
+    None
+}
+
 // https://en.wikipedia.org/wiki/Symplectic_integrator
+// Accumulates accelerations for the pairs between start and end (inclusive) into acceleration_vectors
 pub fn symplectic_mt(
     local_bodies: &[AstronomicalObject],
     start: (usize, usize),
     end: (usize, usize),
-) -> Result<Vec<DVec3>, (usize, usize)> {
+    acceleration_vectors: &mut [DVec3],
+    softening: f64,
+) -> Result<(), (usize, usize)> {
     let num_bodies = local_bodies.len();
-    let mut acceleration_vectors = vec![DVec3::ZERO; num_bodies];
 
     let start_i = start.0;
     let start_j = start.1;
@@ -159,17 +270,20 @@ pub fn symplectic_mt(
             if distance <= a.radius + b.radius {
                 return Err((first, second)); // Process collisions before update results
             }
-            let grav_mult = G / (distance.powi(3)); // Divide by r^3 to get a unit vector out of difference
+            let grav_mult = G / softened_distance_cubed(distance, softening); // Divide by r^3 to get a unit vector out of difference
 
             acceleration_vectors[first] += grav_mult * b.mass * difference;
             acceleration_vectors[second] += -grav_mult * a.mass * difference;
         }
     }
 
-    Ok(acceleration_vectors)
+    Ok(())
 }
 
-pub fn symplectic(local_bodies: &[AstronomicalObject]) -> Result<Vec<DVec3>, (usize, usize)> {
+pub fn symplectic(
+    local_bodies: &[AstronomicalObject],
+    softening: f64,
+) -> Result<Vec<DVec3>, (usize, usize)> {
     let num_bodies = local_bodies.len();
     let mut acceleration_vectors = vec![DVec3::ZERO; num_bodies];
 
@@ -183,7 +297,7 @@ pub fn symplectic(local_bodies: &[AstronomicalObject]) -> Result<Vec<DVec3>, (us
             if distance <= a.radius + b.radius {
                 return Err((first, second)); // Process collisions before update results
             }
-            let grav_mult = G / (distance.powi(3)); // Divide by r^3 to get a unit vector out of difference
+            let grav_mult = G / softened_distance_cubed(distance, softening); // Divide by r^3 to get a unit vector out of difference
 
             acceleration_vectors[first] += grav_mult * b.mass * difference;
             acceleration_vectors[second] += -grav_mult * a.mass * difference;
@@ -193,10 +307,403 @@ pub fn symplectic(local_bodies: &[AstronomicalObject]) -> Result<Vec<DVec3>, (us
     Ok(acceleration_vectors)
 }
 
+// Accelerations split into the parts caused by bodies of the same group and by all other bodies
+pub fn grouped_accelerations(
+    local_bodies: &[AstronomicalObject],
+    groups: &[u8],
+    softening: f64,
+    num_threads: usize,
+) -> Result<(Vec<DVec3>, Vec<DVec3>), (usize, usize)> {
+    let num_bodies = local_bodies.len();
+    if num_threads > 1 && num_bodies > 1 {
+        return grouped_accelerations_mt(local_bodies, groups, softening, num_threads);
+    }
+
+    let mut internal = vec![DVec3::ZERO; num_bodies];
+    let mut external = vec![DVec3::ZERO; num_bodies];
+
+    for first in 0..num_bodies.saturating_sub(1) {
+        for second in first + 1..num_bodies {
+            let (a, b) = (&local_bodies[first], &local_bodies[second]);
+
+            let difference = b.position - a.position;
+            let distance = difference.length();
+
+            if distance <= a.radius + b.radius {
+                return Err((first, second));
+            }
+            let grav_mult = G / softened_distance_cubed(distance, softening);
+
+            let accelerations = match groups[first] == groups[second] {
+                true => &mut internal,
+                false => &mut external,
+            };
+            accelerations[first] += grav_mult * b.mass * difference;
+            accelerations[second] += -grav_mult * a.mass * difference;
+        }
+    }
+
+    Ok((internal, external))
+}
+
+// Threads sum whole rows, so every pair is evaluated twice but nothing is shared. Each body adds the others in
+// index order like the pairwise loop does, which keeps the results identical to a single thread
+fn grouped_accelerations_mt(
+    local_bodies: &[AstronomicalObject],
+    groups: &[u8],
+    softening: f64,
+    num_threads: usize,
+) -> Result<(Vec<DVec3>, Vec<DVec3>), (usize, usize)> {
+    let num_bodies = local_bodies.len();
+    let rows = |start: usize, end: usize| -> Result<Vec<(DVec3, DVec3)>, (usize, usize)> {
+        (start..end)
+            .map(|first| {
+                let a = &local_bodies[first];
+                let (mut internal, mut external) = (DVec3::ZERO, DVec3::ZERO);
+
+                for (second, b) in local_bodies.iter().enumerate() {
+                    if second == first {
+                        continue;
+                    }
+                    let difference = b.position - a.position;
+                    let distance = difference.length();
+
+                    // Only checked from the lower index, an earlier row already reports the other pairs
+                    if second > first && distance <= a.radius + b.radius {
+                        return Err((first, second));
+                    }
+                    let grav_mult = G / softened_distance_cubed(distance, softening);
+
+                    match groups[first] == groups[second] {
+                        true => internal += grav_mult * b.mass * difference,
+                        false => external += grav_mult * b.mass * difference,
+                    }
+                }
+
+                Ok((internal, external))
+            })
+            .collect()
+    };
+
+    let chunk_size = num_bodies.div_ceil(num_threads);
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..num_bodies)
+            .step_by(chunk_size)
+            .map(|start| {
+                let rows = &rows;
+                scope.spawn(move || rows(start, (start + chunk_size).min(num_bodies)))
+            })
+            .collect();
+
+        // Joined in order, so the reported collision is the lowest pair like with a single thread
+        let mut internal = Vec::with_capacity(num_bodies);
+        let mut external = Vec::with_capacity(num_bodies);
+        for handle in handles {
+            for (a, b) in handle.join().unwrap()? {
+                internal.push(a);
+                external.push(b);
+            }
+        }
+
+        Ok((internal, external))
+    })
+}
+
+// One step where every body is integrated with the symplectic order of its group. Forces between groups and the
+// external field are applied as half kicks before and after the groups move, so a tightly bound group can use a
+// higher order than the rest. Impulses between groups stay equal and opposite, so momentum is conserved.
+// A collision at any stage rolls the step back before it is reported
+pub fn symplectic_grouped(
+    local_bodies: &mut [AstronomicalObject],
+    orders: &[u8],
+    time_step: f64,
+    field: &ExternalField,
+    time: f64,
+    softening: f64,
+    num_threads: usize,
+) -> Option<(usize, usize)> {
+    let start: Vec<(DVec3, DVec3)> = local_bodies
+        .iter()
+        .map(|x| (x.position, x.velocity))
+        .collect();
+
+    let result = grouped_step(
+        local_bodies,
+        orders,
+        time_step,
+        field,
+        time,
+        softening,
+        num_threads,
+    );
+
+    if result.is_err() {
+        for (body, (position, velocity)) in local_bodies.iter_mut().zip(start) {
+            body.position = position;
+            body.velocity = velocity;
+        }
+    }
+
+    result.err()
+}
+
+fn grouped_step(
+    local_bodies: &mut [AstronomicalObject],
+    orders: &[u8],
+    time_step: f64,
+    field: &ExternalField,
+    time: f64,
+    softening: f64,
+    num_threads: usize,
+) -> Result<(), (usize, usize)> {
+    let (_, external) = grouped_accelerations(local_bodies, orders, softening, num_threads)?;
+    for (body, acceleration) in local_bodies.iter_mut().zip(external) {
+        let acceleration = acceleration + field.acceleration(body.position, time);
+        body.velocity += 0.5 * time_step * acceleration;
+    }
+
+    // Groups only feel their own forces here, so their stages don't need to line up.
+    // Shorter tables are padded with empty stages
+    let tables: Vec<Vec<(f64, f64)>> = orders
+        .iter()
+        .map(|k| IntegrationMethod::Symplectic(*k).get_coefficients())
+        .collect();
+    let stages = tables.iter().map(|x| x.len()).max().unwrap_or(0);
+
+    for stage in 0..stages {
+        let coefficients: Vec<(f64, f64)> = tables
+            .iter()
+            .map(|x| x.get(stage).copied().unwrap_or((0.0, 0.0)))
+            .collect();
+
+        for (body, (c, _)) in local_bodies.iter_mut().zip(&coefficients) {
+            body.position += time_step * c * body.velocity;
+        }
+
+        if coefficients.iter().any(|(_, d)| *d != 0.0) {
+            let (internal, _) =
+                grouped_accelerations(local_bodies, orders, softening, num_threads)?;
+            for ((body, (_, d)), acceleration) in
+                local_bodies.iter_mut().zip(&coefficients).zip(internal)
+            {
+                body.velocity += time_step * d * acceleration;
+            }
+        }
+    }
+
+    let (internal, external) = grouped_accelerations(local_bodies, orders, softening, num_threads)?;
+    for ((body, internal), external) in local_bodies.iter_mut().zip(internal).zip(external) {
+        let external = external + field.acceleration(body.position, time + time_step);
+        body.velocity += 0.5 * time_step * external;
+        body.acceleration = internal + external;
+    }
+
+    Ok(())
+}
+
+// Shortest time until two approaching bodies touch, assuming straight line motion. None if nothing is approaching
+pub fn min_encounter_time(objects: &[AstronomicalObject]) -> Option<f64> {
+    let mut min_time: Option<f64> = None;
+
+    for (i, a) in objects.iter().enumerate() {
+        for b in &objects[i + 1..] {
+            let difference = b.position - a.position;
+            let distance = difference.length();
+            let closing_speed = -difference.dot(b.velocity - a.velocity) / distance;
+
+            if closing_speed <= 0.0 {
+                continue;
+            }
+
+            let time = (distance - a.radius - b.radius).max(0.0) / closing_speed;
+            min_time = Some(min_time.map_or(time, |t| t.min(time)));
+        }
+    }
+
+    min_time
+}
+
+pub fn kinetic_energy(objects: &[AstronomicalObject]) -> f64 {
+    objects
+        .iter()
+        .map(|x| 0.5 * x.mass * x.velocity.length_squared())
+        .sum()
+}
+
+// Potential energy of a single body in the field of all the others
+pub fn body_potential_energy(
+    body: &AstronomicalObject,
+    objects: &[AstronomicalObject],
+    softening: f64,
+) -> f64 {
+    objects
+        .iter()
+        .filter(|x| x.uuid != body.uuid)
+        .map(|x| {
+            -G * x.mass * body.mass
+                / (x.position.distance_squared(body.position) + softening.powi(2)).sqrt()
+        })
+        .sum()
+}
+
+pub fn potential_energy(objects: &[AstronomicalObject], softening: f64) -> f64 {
+    let mut energy = 0.0;
+    for (i, a) in objects.iter().enumerate() {
+        for b in &objects[i + 1..] {
+            energy -= G * a.mass * b.mass
+                / (a.position.distance_squared(b.position) + softening.powi(2)).sqrt();
+        }
+    }
+
+    energy
+}
+
+// Advances a relative two-body state by dt using f and g functions (Battin).
+// Only elliptic orbits are solved, anything else keeps moving in a straight line.
+pub fn kepler_propagate(position: DVec3, velocity: DVec3, mu: f64, dt: f64) -> (DVec3, DVec3) {
+    let r0 = position.length();
+    let a = 1.0 / (2.0 / r0 - velocity.length_squared() / mu);
+
+    if !(a > 0.0 && a.is_finite()) || dt == 0.0 {
+        return (position + velocity * dt, velocity);
+    }
+
+    let sqrt_a = a.sqrt();
+    let sigma0 = position.dot(velocity) / mu.sqrt();
+    let mean_motion = (mu / a.powi(3)).sqrt();
+
+    // Only the fraction within one period matters
+    let period = 2.0 * std::f64::consts::PI / mean_motion;
+    let dt_wrapped = dt % period;
+    let mean_anomaly = mean_motion * dt_wrapped;
+
+    // Solve Kepler's equation for the change in eccentric anomaly
+    let mut de = mean_anomaly;
+    for _ in 0..50 {
+        let (sin_de, cos_de) = de.sin_cos();
+        let f = de - (1.0 - r0 / a) * sin_de + sigma0 / sqrt_a * (1.0 - cos_de) - mean_anomaly;
+        let df = 1.0 - (1.0 - r0 / a) * cos_de + sigma0 / sqrt_a * sin_de;
+        let step = f / df;
+        de -= step;
+
+        if step.abs() < 1.0E-12 {
+            break;
+        }
+    }
+
+    let (sin_de, cos_de) = de.sin_cos();
+    let r = a + (r0 - a) * cos_de + sigma0 * sqrt_a * sin_de;
+
+    let f = 1.0 - a / r0 * (1.0 - cos_de);
+    let g = a * sigma0 / mu.sqrt() * (1.0 - cos_de) + r0 * (a / mu).sqrt() * sin_de;
+    let f_dot = -(mu * a).sqrt() / (r * r0) * sin_de;
+    let g_dot = 1.0 - a / r * (1.0 - cos_de);
+
+    (
+        f * position + g * velocity,
+        f_dot * position + g_dot * velocity,
+    )
+}
+
+// Gravitational parameter of the relative orbit of a pair
+fn pair_mu(a: &AstronomicalObject, b: &AstronomicalObject) -> f64 {
+    G * (a.mass + b.mass)
+}
+
+// Whether kepler_two_body can solve the pair, judged with the same parameter it propagates with
+pub fn is_bound_pair(a: &AstronomicalObject, b: &AstronomicalObject) -> bool {
+    let distance = a.position.distance(b.position);
+    let relative_speed = a.velocity.distance(b.velocity);
+
+    relative_speed.powi(2) / 2.0 - pair_mu(a, b) / distance < 0.0
+}
+
+// Whether an elliptic relative orbit goes through periapsis within dt while periapsis is closer than distance
+fn passes_periapsis_within(
+    position: DVec3,
+    velocity: DVec3,
+    mu: f64,
+    dt: f64,
+    distance: f64,
+) -> bool {
+    let r0 = position.length();
+    let a = 1.0 / (2.0 / r0 - velocity.length_squared() / mu);
+    let eccentricity_vector =
+        ((velocity.length_squared() - mu / r0) * position - position.dot(velocity) * velocity) / mu;
+    let e = eccentricity_vector.length();
+
+    if !(a > 0.0 && a.is_finite()) || a * (1.0 - e) > distance {
+        return false;
+    }
+
+    let mean_motion = (mu / a.powi(3)).sqrt();
+    if e < 1.0E-12 || mean_motion * dt >= 2.0 * std::f64::consts::PI {
+        return true;
+    }
+
+    // Mean anomaly grows steadily from 0 at periapsis
+    let cos_e = (1.0 - r0 / a) / e;
+    let sin_e = position.dot(velocity) / (e * (mu * a).sqrt());
+    let eccentric_anomaly = sin_e.atan2(cos_e);
+    let mean_anomaly =
+        (eccentric_anomaly - e * eccentric_anomaly.sin()).rem_euclid(2.0 * std::f64::consts::PI);
+
+    mean_anomaly + mean_motion * dt >= 2.0 * std::f64::consts::PI
+}
+
+// Exact step for a bound pair, both bodies follow Kepler orbits around their barycenter.
+// Softening and external fields are not taken into account
+pub fn kepler_two_body(
+    local_bodies: &mut [AstronomicalObject],
+    time_step: f64,
+) -> Option<(usize, usize)> {
+    let (first, second) = local_bodies.split_at_mut(1);
+    let (a, b) = (&mut first[0], &mut second[0]);
+
+    let total_mass = a.mass + b.mass;
+    let mu = pair_mu(a, b);
+    let com_position = (a.position * a.mass + b.position * b.mass) / total_mass;
+    let com_velocity = (a.velocity * a.mass + b.velocity * b.mass) / total_mass;
+
+    // Checking only the end of the step would miss a close periapsis passage in between
+    let grazing = passes_periapsis_within(
+        b.position - a.position,
+        b.velocity - a.velocity,
+        mu,
+        time_step,
+        a.radius + b.radius,
+    );
+
+    let (position, velocity) = kepler_propagate(
+        b.position - a.position,
+        b.velocity - a.velocity,
+        mu,
+        time_step,
+    );
+    let com_position = com_position + com_velocity * time_step;
+
+    a.position = com_position - position * (b.mass / total_mass);
+    a.velocity = com_velocity - velocity * (b.mass / total_mass);
+    b.position = com_position + position * (a.mass / total_mass);
+    b.velocity = com_velocity + velocity * (a.mass / total_mass);
+
+    let distance = position.length();
+    a.acceleration = G * b.mass / distance.powi(3) * position;
+    b.acceleration = -G * a.mass / distance.powi(3) * position;
+
+    if grazing || distance <= a.radius + b.radius {
+        return Some((0, 1));
+    }
+
+    None
+}
+
+// Merges the lighter body into the heavier one. Returns the index of the survivor after removal and the absorbed body
 pub fn collide_objects(
     local_objects: &mut Vec<AstronomicalObject>,
     (first, second): &(usize, usize),
-) {
+    blend_colors: bool,
+) -> (usize, AstronomicalObject) {
     let obs = local_objects;
     let (h, l) = if obs[*first].mass >= obs[*second].mass {
         (*first, *second)
@@ -210,9 +717,211 @@ pub fn collide_objects(
     obs[h].position =
         obs[h].position + (obs[l].position - obs[h].position) * (obs[l].mass / total_mass);
 
+    if blend_colors {
+        // Mass weighted, so a small impactor only tints the survivor slightly
+        let weight = obs[l].mass / total_mass;
+        for i in 0..3 {
+            let blended = obs[h].color[i] as f64 * (1.0 - weight) + obs[l].color[i] as f64 * weight;
+            obs[h].color[i] = blended.round() as u8;
+        }
+    }
+
     obs[h].mass += obs[l].mass;
     obs[h].radius *= (total_mass / obs[h].mass).powf(3.0_f64.recip());
 
-    println!("{} collided into {}!", obs[l].name, obs[h].name);
-    obs.remove(l);
+    let absorbed = obs.remove(l);
+
+    (if h > l { h - 1 } else { h }, absorbed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::engine::Engine;
+
+    #[test]
+    fn uniform_field_moves_barycenter() {
+        let speed = (G * 2.0E30 / 1.5E11).sqrt();
+        let mut objects = vec![
+            AstronomicalObject::test_body(2.0E30, DVec3::ZERO, DVec3::ZERO),
+            AstronomicalObject::test_body(
+                6.0E24,
+                DVec3::new(1.5E11, 0.0, 0.0),
+                DVec3::new(0.0, 0.0, speed),
+            ),
+            AstronomicalObject::test_body(
+                2.0E27,
+                DVec3::new(0.0, 0.0, -7.8E11),
+                DVec3::new(13.0E3, 0.0, 0.0),
+            ),
+        ];
+        let field_acceleration = DVec3::new(1.0E-10, -2.0E-10, 3.0E-10);
+        let field = ExternalField::Uniform(field_acceleration);
+
+        let (start_position, start_velocity) = Engine::get_center_of_mass(&objects);
+        let time_step = 3600.0;
+        let steps = 24 * 365;
+        for step in 0..steps {
+            let collision = runge_kutta_4(
+                &mut objects,
+                time_step,
+                &field,
+                step as f64 * time_step,
+                0.0,
+            );
+            assert!(collision.is_none());
+        }
+
+        // Mutual forces cancel, so the barycenter accelerates exactly like a free particle in the field
+        let time = steps as f64 * time_step;
+        let (position, velocity) = Engine::get_center_of_mass(&objects);
+        let expected_position =
+            start_position + start_velocity * time + 0.5 * field_acceleration * time.powi(2);
+        let expected_velocity = start_velocity + field_acceleration * time;
+
+        assert!(
+            (position - expected_position).length()
+                < 1.0E-6 * (expected_position - start_position).length()
+        );
+        assert!(
+            (velocity - expected_velocity).length()
+                < 1.0E-6 * (expected_velocity - start_velocity).length()
+        );
+    }
+
+    // Sun and a body on an orbit with e = 0.5, starting at periapsis with the barycenter at rest.
+    // Returns the bodies and the period
+    fn eccentric_pair() -> (Vec<AstronomicalObject>, f64) {
+        let (sun_mass, planet_mass) = (2.0E30, 6.0E26);
+        let mu = G * (sun_mass + planet_mass);
+        let (semi_major_axis, eccentricity) = (1.5E11, 0.5);
+
+        let periapsis = semi_major_axis * (1.0 - eccentricity);
+        let speed = (mu * (2.0 / periapsis - 1.0 / semi_major_axis)).sqrt();
+        let sun_share = planet_mass / (sun_mass + planet_mass);
+        let objects = vec![
+            AstronomicalObject::test_body(
+                sun_mass,
+                DVec3::ZERO,
+                DVec3::new(0.0, 0.0, -speed * sun_share),
+            ),
+            AstronomicalObject::test_body(
+                planet_mass,
+                DVec3::new(periapsis, 0.0, 0.0),
+                DVec3::new(0.0, 0.0, speed * (1.0 - sun_share)),
+            ),
+        ];
+        let period = 2.0 * std::f64::consts::PI * (semi_major_axis.powi(3) / mu).sqrt();
+
+        (objects, period)
+    }
+
+    #[test]
+    fn kepler_matches_numerical_integration() {
+        let (start, period) = eccentric_pair();
+        let steps = 20000;
+        let time_step = period / steps as f64;
+
+        let mut exact = start.clone();
+        let mut numerical = start.clone();
+        for step in 0..steps {
+            assert!(kepler_two_body(&mut exact, time_step).is_none());
+            assert!(runge_kutta_4(
+                &mut numerical,
+                time_step,
+                &ExternalField::Off,
+                step as f64 * time_step,
+                0.0
+            )
+            .is_none());
+        }
+
+        // Both should be back where they started after one period
+        let tolerance = 1.0E-6 * 1.5E11;
+        let velocity_tolerance = 1.0E-6 * start[1].velocity.length();
+        for ((exact, numerical), start) in exact.iter().zip(numerical.iter()).zip(start.iter()) {
+            assert!(exact.position.distance(numerical.position) < tolerance);
+            assert!(exact.position.distance(start.position) < tolerance);
+            assert!(exact.velocity.distance(numerical.velocity) < velocity_tolerance);
+        }
+    }
+
+    #[test]
+    fn kepler_finds_collision_between_steps() {
+        let (mut objects, period) = eccentric_pair();
+        // Touching only around periapsis, 0.75 AU from the center
+        objects[0].radius = 0.8E11;
+
+        // Half a period from apoapsis doesn't reach periapsis yet, a bit more steps over it
+        let mut apoapsis = objects.clone();
+        kepler_two_body(&mut apoapsis, period / 2.0);
+        assert!(kepler_two_body(&mut apoapsis.clone(), 0.4 * period).is_none());
+        assert_eq!(kepler_two_body(&mut apoapsis, 0.6 * period), Some((0, 1)));
+        assert!(is_bound_pair(&apoapsis[0], &apoapsis[1]));
+    }
+
+    #[test]
+    fn grouped_orders_conserve_momentum() {
+        let earth_speed = (G * 2.0E30 / 1.5E11).sqrt();
+        let moon_speed = (G * 6.0E24 / 3.84E8).sqrt();
+        let mut objects = vec![
+            AstronomicalObject::test_body(2.0E30, DVec3::ZERO, DVec3::ZERO),
+            AstronomicalObject::test_body(
+                6.0E24,
+                DVec3::new(1.5E11, 0.0, 0.0),
+                DVec3::new(0.0, 0.0, earth_speed),
+            ),
+            AstronomicalObject::test_body(
+                7.3E22,
+                DVec3::new(1.5E11 + 3.84E8, 0.0, 0.0),
+                DVec3::new(0.0, 0.0, earth_speed + moon_speed),
+            ),
+            AstronomicalObject::test_body(
+                2.0E27,
+                DVec3::new(0.0, 0.0, -7.8E11),
+                DVec3::new(13.0E3, 0.0, 0.0),
+            ),
+        ];
+        // Earth and moon are the tightly bound group
+        let orders = [1, 4, 4, 2];
+
+        let momentum = |objects: &[AstronomicalObject]| {
+            objects.iter().map(|x| x.velocity * x.mass).sum::<DVec3>()
+        };
+        let scale: f64 = objects.iter().map(|x| x.mass * x.velocity.length()).sum();
+        let start = momentum(&objects);
+
+        let time_step = 3600.0;
+        for step in 0..24 * 365 {
+            let collision = symplectic_grouped(
+                &mut objects,
+                &orders,
+                time_step,
+                &ExternalField::Off,
+                step as f64 * time_step,
+                0.0,
+                1,
+            );
+            assert!(collision.is_none());
+        }
+
+        // Every impulse between groups has an equal and opposite one
+        let error = (momentum(&objects) - start).length() / scale;
+        assert!(error < 1.0E-12, "momentum error: {}", error);
+
+        // Forces between the groups keep the earth around the sun, the moon stays with the earth
+        let distance = objects[1].position.distance(objects[0].position);
+        assert!(
+            (distance - 1.5E11).abs() < 0.01 * 1.5E11,
+            "earth distance: {}",
+            distance
+        );
+        let distance = objects[2].position.distance(objects[1].position);
+        assert!(
+            (distance - 3.84E8).abs() < 0.05 * 3.84E8,
+            "moon distance: {}",
+            distance
+        );
+    }
 }
@@ -5,26 +5,32 @@ mod input;
 mod integration;
 mod events;
 mod ui;
+mod lock;
+mod export;
+mod octree;
 
-use crate::engine::Engine;
+use crate::engine::{Engine, SimulationEvent, SimulationState, SimulatorControl};
 use crate::camera::Camera;
+use crate::lock::LockRecover;
 
 extern crate native_windows_gui as nwg;
 extern crate native_windows_derive as nwd;
 
-use astronomy::AstronomicalObject;
 use nwd::NwgUi;
 use nwg::{NativeUi, ExternCanvas, Window};
 use rand::SeedableRng;
-use ui::TargetData;
+use ui::{OverlayLabel, OverlayLine, PerihelionTracker, RenderOptions, TargetData};
 use uuid::Uuid;
 use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Receiver;
 use std::time::{Duration, Instant};
 use std::mem;
+use std::ptr;
 use std::cell::RefCell;
 use std::sync::{Mutex, Arc};
-use winapi::shared::windef::{HBRUSH, HPEN, HFONT};
-use winapi::um::wingdi::{CreateSolidBrush, CreatePen, PS_SOLID, CreateFontW, FW_NORMAL, DEFAULT_CHARSET, OUT_DEFAULT_PRECIS, CLIP_DEFAULT_PRECIS, DEFAULT_QUALITY, DEFAULT_PITCH, FF_DONTCARE, FW_BOLD, RGB};
+use winapi::shared::windef::{HBRUSH, HPEN, HFONT, HBITMAP};
+use winapi::um::winuser::{LoadImageW, IMAGE_BITMAP, LR_LOADFROMFILE};
+use winapi::um::wingdi::{BITMAP, GetObjectW, CreateSolidBrush, CreatePen, PS_SOLID, CreateFontW, FW_NORMAL, DEFAULT_CHARSET, OUT_DEFAULT_PRECIS, CLIP_DEFAULT_PRECIS, DEFAULT_QUALITY, DEFAULT_PITCH, FF_DONTCARE, FW_BOLD, RGB};
 
 const FRAMERATE: u32 = 100;
 
@@ -44,6 +50,22 @@ pub struct Color {
     brush: HBRUSH
 }
 
+#[derive(Debug)]
+pub struct Pen {
+    r: u8,
+    g: u8,
+    b: u8,
+    pen: HPEN
+}
+
+#[derive(Debug)]
+pub struct Texture {
+    path: String,
+    bitmap: Option<HBITMAP>, // None if loading failed, so it isn't retried every frame
+    width: i32,
+    height: i32
+}
+
 impl Default for PaintData {
     fn default() -> Self {
         unsafe { mem::zeroed() }
@@ -58,7 +80,8 @@ pub struct DrawingApp {
         OnInit: [DrawingApp::setup], 
         OnResize: [DrawingApp::update_size], 
         OnWindowMaximize: [DrawingApp::update_size],
-        OnKeyPress: [DrawingApp::events(SELF, EVT, EVT_DATA)]
+        OnKeyPress: [DrawingApp::events(SELF, EVT, EVT_DATA)],
+        OnKeyEnter: [DrawingApp::apply_speed_input]
     )]
     window: nwg::Window,
 
@@ -70,22 +93,39 @@ pub struct DrawingApp {
         OnMouseWheel: [DrawingApp::events(SELF, EVT, EVT_DATA)],
     )]
     canvas: nwg::ExternCanvas,
+
+    // Exact value for the time step or target speed, applied with Enter. Created after the canvas so it stays on top
+    #[nwg_control(parent: window, position: (770, 10), size: (180, 25), placeholder_text: Some("Time step (s)"))]
+    speed_input: nwg::TextInput,
     paint_data: RefCell<PaintData>,
 
     engine: Engine,
+    simulation_events: Receiver<SimulationEvent>,
 
     camera: Arc<Mutex<Camera>>,
     is_dragging: Arc<AtomicBool>,
     current_target: RefCell<Option<Uuid>>,
+    focus_group: RefCell<Vec<Uuid>>, // Camera follows the barycenter of these bodies when not empty
+    measurement: RefCell<Option<Vec<Uuid>>>, // Some while measuring, holds up to two picked bodies
+    checkpoint: RefCell<Option<SimulationState>>,
     targets: RefCell<Vec<TargetData>>,
+    overlay_lines: RefCell<Vec<OverlayLine>>,
+    overlay_labels: RefCell<Vec<OverlayLabel>>,
+    perihelia: RefCell<PerihelionTracker>,
     colors: RefCell<Vec<Color>>,
+    pens: RefCell<Vec<Pen>>,
+    textures: RefCell<Vec<Texture>>,
+    render_options: RefCell<RenderOptions>,
 
     #[nwg_control(parent: window, interval: Duration::from_micros(1_000_000 / FRAMERATE as u64))]
     #[nwg_events( OnTimerTick: [DrawingApp::inv] )]
     animation_timer: nwg::AnimationTimer,
     next_status_update: RefCell<Instant>,
     status_lines: RefCell<Vec<String>>,
-    object_description: RefCell<Vec<String>>
+    object_description: RefCell<Vec<String>>,
+    hierarchy_lines: RefCell<Vec<String>>,
+    random_cloud: RefCell<Vec<Uuid>>, // Bodies replaced when the cloud is rerolled
+    cloud_seed: RefCell<Option<u64>>
 }
 
 impl DrawingApp {
@@ -146,10 +186,15 @@ impl DrawingApp {
     fn update_size(&self) {
         let (x, y) = self.window.size();
         self.canvas.set_size(x, y);
+        self.speed_input.set_position(x as i32 - 190, 10); // Top right, status text is at the bottom
+    }
+
+    fn apply_speed_input(&self) {
+        events::apply_speed_input(self);
     }
 
     fn zoom(&self, amount: i32) {
-        let mut camera = self.camera.lock().unwrap();
+        let mut camera = self.camera.lock_recover();
         camera.zoom(amount);
     }
 
@@ -169,11 +214,80 @@ impl DrawingApp {
             brush
         }        
     }
+
+    fn get_pen(&self, r: u8, g: u8, b: u8) -> HPEN {
+        let mut pens = self.pens.borrow_mut();
+        for p in pens.iter() {
+            if r == p.r && g == p.g && b == p.b {
+                return p.pen;
+            }
+        }
+
+        unsafe {
+            let pen = CreatePen(PS_SOLID as _, 1, RGB(r, g, b));
+
+            pens.push(Pen {r, g, b, pen});
+            pen
+        }
+    }
+
+    fn get_texture(&self, path: &str) -> Option<(HBITMAP, i32, i32)> {
+        let mut textures = self.textures.borrow_mut();
+        for t in textures.iter() {
+            if t.path == path {
+                return t.bitmap.map(|b| (b, t.width, t.height));
+            }
+        }
+
+        let mut new_texture = Texture {path: path.to_string(), bitmap: None, width: 0, height: 0};
+
+        unsafe {
+            let wide_path = path.encode_utf16().chain(Some(0)).collect::<Vec<u16>>();
+            let handle = LoadImageW(ptr::null_mut(), wide_path.as_ptr(), IMAGE_BITMAP, 0, 0, LR_LOADFROMFILE);
+
+            if handle.is_null() {
+                eprintln!("Failed to load texture {}", path);
+            } else {
+                let mut bitmap: BITMAP = mem::zeroed();
+                GetObjectW(handle, mem::size_of::<BITMAP>() as i32, &mut bitmap as *mut BITMAP as _);
+
+                new_texture.bitmap = Some(handle as HBITMAP);
+                new_texture.width = bitmap.bmWidth;
+                new_texture.height = bitmap.bmHeight;
+            }
+        }
+
+        let result = new_texture.bitmap.map(|b| (b, new_texture.width, new_texture.height));
+        textures.push(new_texture);
+        result
+    }
+}
+
+// --stop-at-time <simulated seconds> and --stop-at-step <steps> make the simulation stop by itself
+fn apply_arguments(params: &mut SimulatorControl) {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_default();
+        match arg.as_str() {
+            "--stop-at-time" => match value.parse::<f64>() {
+                Ok(t) if t.is_finite() && t > 0.0 => params.stop_at_time = Some(t),
+                _ => eprintln!("Invalid time for {}: {}", arg, value),
+            },
+            "--stop-at-step" => match value.parse::<u128>() {
+                Ok(n) => params.stop_at_step = Some(n),
+                Err(_) => eprintln!("Invalid step count for {}: {}", arg, value),
+            },
+            _ => eprintln!("Unknown argument: {}", arg),
+        }
+    }
 }
 
 fn main() {
     let mut rng = rand::rngs::StdRng::seed_from_u64(42);
 
+    let engine = Engine::default(&mut rng);
+    let simulation_events = engine.subscribe();
+
     nwg::init().expect("Failed to init Native Windows GUI");
     nwg::Font::set_global_family("Segoe UI").expect("Failed to set default font");
 
@@ -182,29 +296,38 @@ fn main() {
         camera: Arc::new(Mutex::new(Camera::default())),
         window: Window::default(),
         canvas: ExternCanvas::default(),
+        speed_input: nwg::TextInput::default(),
         paint_data: RefCell::new(PaintData::default()),
-        engine: Engine::default(&mut rng),
+        engine,
+        simulation_events,
         is_dragging: Arc::new(AtomicBool::new(false)),
         current_target: RefCell::new(None),
+        focus_group: RefCell::new(Vec::new()),
+        measurement: RefCell::new(None),
+        checkpoint: RefCell::new(None),
         targets: RefCell::new(Vec::new()),
+        overlay_lines: RefCell::new(Vec::new()),
+        overlay_labels: RefCell::new(Vec::new()),
+        perihelia: RefCell::new(PerihelionTracker::default()),
         colors: RefCell::new(Vec::new()),
+        pens: RefCell::new(Vec::new()),
+        textures: RefCell::new(Vec::new()),
+        render_options: RefCell::new(RenderOptions::default()),
         next_status_update: RefCell::new(Instant::now()),
         status_lines: RefCell::new(Vec::new()),
-        object_description: RefCell::new(Vec::new())
+        object_description: RefCell::new(Vec::new()),
+        hierarchy_lines: RefCell::new(Vec::new()),
+        random_cloud: RefCell::new(Vec::new()),
+        cloud_seed: RefCell::new(None)
     };
 
     let app_ui = DrawingApp::build_ui(app).expect("Failed to build UI");
 
-    *app_ui.engine.framerate.lock().unwrap() = FRAMERATE;
-    app_ui.engine.params.lock().unwrap().target_speed = 86400.0 * 1.0;
+    *app_ui.engine.framerate.lock_recover() = FRAMERATE;
+    app_ui.engine.params.lock_recover().target_speed = 86400.0 * 1.0;
 
-    for _ in 0..2000 {
-        let mut objects = app_ui.engine.objects.lock().unwrap();
-        let orbital = AstronomicalObject::get_random_planet(&mut rng);
-        let object = AstronomicalObject::place_on_orbit(orbital, &objects[0], &mut rng);
-
-        objects.push(object);
-    }
+    events::load_random_cloud(&app_ui, 42);
+    apply_arguments(&mut app_ui.engine.params.lock_recover());
     
     app_ui.animation_timer.start();        
     nwg::dispatch_thread_events();
@@ -0,0 +1,242 @@
+use std::thread;
+
+use glam::DVec3;
+
+use crate::astronomy::AstronomicalObject;
+use crate::integration::{softened_distance_cubed, G};
+
+// Nodes with at most this many bodies aren't subdivided further
+const LEAF_CAPACITY: usize = 1;
+// Bodies at (nearly) the same position would otherwise be subdivided forever
+const MAX_DEPTH: usize = 32;
+
+struct Node {
+    center: DVec3,
+    half_size: f64,
+    mass: f64,
+    center_of_mass: DVec3,
+    max_radius: f64, // Largest body inside, so overlaps can be found without opening every node
+    children: Vec<Node>, // Only non-empty octants. Empty for leaves
+    bodies: Vec<usize>, // Only filled for leaves
+}
+
+impl Node {
+    fn build(
+        objects: &[AstronomicalObject],
+        bodies: Vec<usize>,
+        center: DVec3,
+        half_size: f64,
+        depth: usize,
+    ) -> Node {
+        let mass: f64 = bodies.iter().map(|i| objects[*i].mass).sum();
+        let center_of_mass = if mass > 0.0 {
+            bodies
+                .iter()
+                .map(|i| objects[*i].position * objects[*i].mass)
+                .sum::<DVec3>()
+                / mass
+        } else {
+            center
+        };
+        let max_radius = bodies
+            .iter()
+            .map(|i| objects[*i].radius)
+            .fold(0.0, f64::max);
+
+        if bodies.len() <= LEAF_CAPACITY || depth == MAX_DEPTH {
+            return Node {
+                center,
+                half_size,
+                mass,
+                center_of_mass,
+                max_radius,
+                children: Vec::new(),
+                bodies,
+            };
+        }
+
+        let mut octants: [Vec<usize>; 8] = Default::default();
+        for i in bodies {
+            let offset = objects[i].position - center;
+            let octant = (offset.x >= 0.0) as usize
+                | ((offset.y >= 0.0) as usize) << 1
+                | ((offset.z >= 0.0) as usize) << 2;
+            octants[octant].push(i);
+        }
+
+        let child_half_size = half_size / 2.0;
+        let children = octants
+            .into_iter()
+            .enumerate()
+            .filter(|(_, bodies)| !bodies.is_empty())
+            .map(|(octant, bodies)| {
+                let sign = |bit: usize| if octant & bit != 0 { 1.0 } else { -1.0 };
+                let child_center = center + DVec3::new(sign(1), sign(2), sign(4)) * child_half_size;
+
+                Node::build(objects, bodies, child_center, child_half_size, depth + 1)
+            })
+            .collect();
+
+        Node {
+            center,
+            half_size,
+            mass,
+            center_of_mass,
+            max_radius,
+            children,
+            bodies: Vec::new(),
+        }
+    }
+
+    fn accumulate(
+        &self,
+        objects: &[AstronomicalObject],
+        index: usize,
+        theta: f64,
+        softening: f64,
+        acceleration: &mut DVec3,
+    ) -> Result<(), (usize, usize)> {
+        let body = &objects[index];
+
+        // Leaves are summed directly, which is also where collisions are found
+        if self.children.is_empty() {
+            for other_index in self.bodies.iter().copied().filter(|x| *x != index) {
+                let other = &objects[other_index];
+
+                let difference = other.position - body.position;
+                let distance = difference.length();
+
+                if distance <= body.radius + other.radius {
+                    return Err((index.min(other_index), index.max(other_index)));
+                }
+                *acceleration +=
+                    G * other.mass / softened_distance_cubed(distance, softening) * difference;
+            }
+
+            return Ok(());
+        }
+
+        let difference = self.center_of_mass - body.position;
+        let distance = difference.length();
+
+        // Distance from the body to the node's cube, 0 when inside it
+        let distance_to_cube = ((body.position - self.center).abs() - self.half_size)
+            .max(DVec3::ZERO)
+            .length();
+
+        // Far enough to be a single point mass, and nothing inside can be touching the body
+        if 2.0 * self.half_size < theta * distance
+            && distance_to_cube > body.radius + self.max_radius
+        {
+            *acceleration +=
+                G * self.mass / softened_distance_cubed(distance, softening) * difference;
+            return Ok(());
+        }
+
+        for child in self.children.iter() {
+            child.accumulate(objects, index, theta, softening, acceleration)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Barnes-Hut approximation of the mutual accelerations, O(n log n) instead of O(n^2).
+// Smaller theta opens more nodes and is more accurate, 0 gives direct summation.
+// Touching bodies are always summed directly and reported like in integration::symplectic.
+// The tree is built once and walked for consecutive ranges of bodies on num_threads threads
+pub fn accelerations(
+    objects: &[AstronomicalObject],
+    theta: f64,
+    softening: f64,
+    num_threads: usize,
+) -> Result<Vec<DVec3>, (usize, usize)> {
+    if objects.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let (min, max) = objects.iter().fold(
+        (DVec3::splat(f64::INFINITY), DVec3::splat(f64::NEG_INFINITY)),
+        |(min, max), x| (min.min(x.position), max.max(x.position)),
+    );
+    let center = (min + max) / 2.0;
+    let half_size = (max - min).max_element() / 2.0;
+
+    let root = Node::build(objects, (0..objects.len()).collect(), center, half_size, 0);
+
+    let walk = |start: usize, end: usize| -> Result<Vec<DVec3>, (usize, usize)> {
+        (start..end)
+            .map(|index| {
+                let mut acceleration = DVec3::ZERO;
+                root.accumulate(objects, index, theta, softening, &mut acceleration)?;
+                Ok(acceleration)
+            })
+            .collect()
+    };
+
+    if num_threads <= 1 {
+        return walk(0, objects.len());
+    }
+
+    let chunk_size = objects.len().div_ceil(num_threads);
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..objects.len())
+            .step_by(chunk_size)
+            .map(|start| {
+                let walk = &walk;
+                scope.spawn(move || walk(start, (start + chunk_size).min(objects.len())))
+            })
+            .collect();
+
+        // Joined in order, so the reported collision is the same as with a single thread
+        let mut accelerations = Vec::with_capacity(objects.len());
+        for handle in handles {
+            accelerations.extend(handle.join().unwrap()?);
+        }
+
+        Ok(accelerations)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::integration;
+
+    fn relative_error(approximate: &[DVec3], exact: &[DVec3]) -> f64 {
+        approximate
+            .iter()
+            .zip(exact)
+            .map(|(a, e)| (*a - *e).length() / e.length())
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn matches_direct_summation_on_default_system() {
+        let objects = AstronomicalObject::default(&mut StdRng::seed_from_u64(42));
+        let exact = integration::symplectic(&objects, 0.0).unwrap();
+
+        let approximate = accelerations(&objects, 0.5, 0.0, 1).unwrap();
+        assert!(relative_error(&approximate, &exact) < 0.03);
+
+        // Splitting the walk between threads gives the same result
+        assert_eq!(accelerations(&objects, 0.5, 0.0, 4).unwrap(), approximate);
+    }
+
+    #[test]
+    fn two_bodies_are_exact() {
+        let body =
+            |mass: f64, position: DVec3| AstronomicalObject::test_body(mass, position, DVec3::ZERO);
+        let objects = vec![
+            body(2.0E30, DVec3::new(-1.0E9, 2.0E9, 0.5E9)),
+            body(6.0E24, DVec3::new(1.5E11, -3.0E9, 1.0E10)),
+        ];
+
+        let exact = integration::symplectic(&objects, 0.0).unwrap();
+        let approximate = accelerations(&objects, 0.5, 0.0, 1).unwrap();
+        assert!(relative_error(&approximate, &exact) < 1.0E-12);
+    }
+}
@@ -1,16 +1,432 @@
-use std::time::{Duration, Instant};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use glam::DVec3;
+use glam::{DAffine3, DVec3};
 use uuid::Uuid;
 use winapi::{
-    shared::windef::HBRUSH,
+    shared::windef::{HBITMAP, HBRUSH, HDC, HGDIOBJ, RECT},
     um::wingdi::{
         BitBlt, CreateCompatibleBitmap, CreateCompatibleDC, DeleteDC, DeleteObject, Ellipse,
-        SelectObject, SetBkMode, SetTextColor, TextOutW, RGB, SRCCOPY, TRANSPARENT,
+        LineTo, MoveToEx, SelectObject, SetBkMode, SetBrushOrgEx, SetGraphicsMode,
+        SetStretchBltMode, SetTextColor, SetWorldTransform, StretchBlt, TextOutW, GM_ADVANCED,
+        HALFTONE, RGB, SRCCOPY, TRANSPARENT, XFORM,
     },
 };
 
-use crate::{engine, integration::IntegrationMethod, DrawingApp};
+use crate::{
+    astronomy::{AstronomicalObject, OrbitalElements, AU},
+    camera::Camera,
+    engine::{self, Accuracy, Boundary, HierarchyNode, SimulationEvent, WatchdogPolicy},
+    integration::{self, ExternalField, IntegrationMethod},
+    lock::LockRecover,
+    DrawingApp,
+};
+
+pub type PaintObject = (
+    i32,
+    i32,
+    i32,
+    i32,
+    HBRUSH,
+    Option<(HBITMAP, i32, i32)>,
+    RenderShape,
+);
+
+// Points and crosses keep dense clouds legible and are cheaper to draw than circles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderShape {
+    Circle,
+    Cross,
+    Point,
+}
+
+impl RenderShape {
+    pub fn next(self) -> RenderShape {
+        match self {
+            RenderShape::Circle => RenderShape::Cross,
+            RenderShape::Cross => RenderShape::Point,
+            RenderShape::Point => RenderShape::Circle,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoundFilter {
+    All,
+    BoundOnly,
+    UnboundOnly,
+}
+
+impl BoundFilter {
+    pub fn next(self) -> BoundFilter {
+        match self {
+            BoundFilter::All => BoundFilter::BoundOnly,
+            BoundFilter::BoundOnly => BoundFilter::UnboundOnly,
+            BoundFilter::UnboundOnly => BoundFilter::All,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RocheDisplay {
+    Off,
+    Selected,
+    Massive,
+}
+
+impl RocheDisplay {
+    pub fn next(self) -> RocheDisplay {
+        match self {
+            RocheDisplay::Off => RocheDisplay::Selected,
+            RocheDisplay::Selected => RocheDisplay::Massive,
+            RocheDisplay::Massive => RocheDisplay::Off,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrbitPlaneDisplay {
+    Off,
+    Selected,
+    All, // Every body on a bound orbit around its orbital parent
+}
+
+impl OrbitPlaneDisplay {
+    pub fn next(self) -> OrbitPlaneDisplay {
+        match self {
+            OrbitPlaneDisplay::Off => OrbitPlaneDisplay::Selected,
+            OrbitPlaneDisplay::Selected => OrbitPlaneDisplay::All,
+            OrbitPlaneDisplay::All => OrbitPlaneDisplay::Off,
+        }
+    }
+}
+
+// Display-only settings toggled from the keyboard
+#[derive(Debug)]
+pub struct RenderOptions {
+    pub bound_filter: BoundFilter,
+    pub doppler: bool,
+    pub sun_lighting: bool,
+    pub log_radial_scale: bool, // Non-physical, compresses distances from the camera target
+    pub limiting_magnitude: Option<f64>, // Bodies dimmer than this aren't drawn
+    pub comet_tails: bool,
+    pub apsis_markers: bool,
+    pub roche_limits: RocheDisplay,
+    pub orbit_planes: OrbitPlaneDisplay, // Tilted orbit outlines, showing relative inclinations
+    pub smooth_overlays: bool,           // Supersampled overlay lines
+    pub depth_cueing: bool,
+    pub depth_cue_gamma: f64, // Higher values dim everything but the nearest bodies more
+    pub render_shape: RenderShape,
+    pub perihelion_marks: bool,
+    pub hierarchy: bool,          // Indented list of which bodies orbit which
+    pub display_precision: usize, // Digits after the decimal point in the info panels
+    pub target_reticle: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            bound_filter: BoundFilter::All,
+            doppler: false,
+            sun_lighting: false,
+            log_radial_scale: false,
+            limiting_magnitude: None,
+            comet_tails: true,
+            apsis_markers: false,
+            roche_limits: RocheDisplay::Off,
+            orbit_planes: OrbitPlaneDisplay::Off,
+            smooth_overlays: false,
+            depth_cueing: false,
+            depth_cue_gamma: 1.0,
+            render_shape: RenderShape::Circle,
+            perihelion_marks: false,
+            hierarchy: false,
+            display_precision: 4,
+            target_reticle: true,
+        }
+    }
+}
+
+// Roche limits are computed for satellites of this density (kg/m^3), roughly a rocky moon
+const ROCHE_SATELLITE_DENSITY: f64 = 3000.0;
+// Bodies at least this fraction of the heaviest body's mass count as massive
+const ROCHE_MASSIVE_FRACTION: f64 = 1.0E-4;
+const CIRCLE_SEGMENTS: usize = 48;
+const HIERARCHY_MAX_LINES: usize = 40;
+pub const MAX_DISPLAY_PRECISION: usize = 12;
+const OVERLAY_SUPERSAMPLING: i32 = 2;
+const CROSS_SIZE: i32 = 3;
+// Camera distance relative to the group's extent when starting to follow a group
+const GROUP_FRAMING: f64 = 4.0;
+
+// Line of sight speed (m/s) that gets fully tinted red or blue
+const DOPPLER_FULL_SHIFT_SPEED: f64 = 30.0E3;
+
+fn blend_color(color: [u8; 3], target: [u8; 3], amount: f64) -> [u8; 3] {
+    let amount = amount.clamp(0.0, 1.0);
+    let mut result = [0u8; 3];
+    for i in 0..3 {
+        result[i] = (color[i] as f64 * (1.0 - amount) + target[i] as f64 * amount).round() as u8;
+    }
+
+    result
+}
+
+// Dimmest allowed brightness with sun lighting, so far bodies don't disappear completely
+const MIN_BRIGHTNESS: f64 = 0.15;
+
+// Brightness of the farthest body with depth cueing
+const DEPTH_CUE_MIN_BRIGHTNESS: f64 = 0.3;
+
+// Depth rank (0 = farthest, 1 = nearest) is used instead of raw distance. This equalizes the depth histogram,
+// so a few very distant bodies don't leave everything else at full brightness
+fn get_depth_cue_brightness(rank: usize, count: usize, gamma: f64) -> f64 {
+    let depth = if count > 1 {
+        rank as f64 / (count - 1) as f64
+    } else {
+        1.0
+    };
+
+    DEPTH_CUE_MIN_BRIGHTNESS + (1.0 - DEPTH_CUE_MIN_BRIGHTNESS) * depth.powf(gamma)
+}
+
+// Received light relative to a body at 1 AU from the light source
+fn get_illumination(body: &AstronomicalObject, light_source: &AstronomicalObject) -> f64 {
+    let distance = body.position.distance(light_source.position);
+    if distance == 0.0 {
+        return f64::INFINITY;
+    }
+
+    (AU / distance).powi(2)
+}
+
+// Magnitude scale where an Earth sized body at 1 AU from both the light source and the camera is 0
+fn get_apparent_magnitude(
+    body: &AstronomicalObject,
+    light_source: &AstronomicalObject,
+    camera_position: DVec3,
+) -> f64 {
+    let earth_radius = 6371.0E3;
+    let camera_distance = body.position.distance(camera_position);
+
+    let flux = (body.radius / earth_radius).powi(2)
+        * get_illumination(body, light_source)
+        * (AU / camera_distance).powi(2);
+
+    -2.5 * flux.log10()
+}
+
+fn scale_brightness(color: [u8; 3], factor: f64) -> [u8; 3] {
+    color.map(|c| (c as f64 * factor).clamp(0.0, 255.0).round() as u8)
+}
+
+// Distances up to about this fraction of the camera distance are left nearly unchanged by the log scale
+const LOG_SCALE_REFERENCE: f64 = 0.05;
+
+// Compresses the distance from focus logarithmically while keeping the direction
+fn log_radial_position(position: DVec3, focus: DVec3, reference: f64) -> DVec3 {
+    let relative = position - focus;
+    let distance = relative.length();
+    if distance == 0.0 {
+        return position;
+    }
+
+    focus + relative * (reference * (distance / reference).ln_1p() / distance)
+}
+
+// Tail length in AU for a comet at 1 AU from the Sun. Scales inversely with distance
+const COMET_TAIL_LENGTH: f64 = 0.3;
+const MAX_COMET_TAIL_LENGTH: f64 = 1.5 * AU;
+const GRADIENT_SEGMENTS: i32 = 8;
+
+// Line drawn in screen coordinates. Colors are interpolated along the line
+pub struct OverlayLine {
+    pub from: (i32, i32),
+    pub to: (i32, i32),
+    pub from_color: [u8; 3],
+    pub to_color: [u8; 3],
+}
+
+// Text drawn at screen coordinates
+pub struct OverlayLabel {
+    pub x: i32,
+    pub y: i32,
+    pub text: String,
+}
+
+const MARKER_SIZE: i32 = 5;
+
+fn push_marker(lines: &mut Vec<OverlayLine>, (x, y): (i32, i32), color: [u8; 3]) {
+    lines.push(OverlayLine {
+        from: (x - MARKER_SIZE, y - MARKER_SIZE),
+        to: (x + MARKER_SIZE + 1, y + MARKER_SIZE + 1),
+        from_color: color,
+        to_color: color,
+    });
+    lines.push(OverlayLine {
+        from: (x - MARKER_SIZE, y + MARKER_SIZE),
+        to: (x + MARKER_SIZE + 1, y - MARKER_SIZE - 1),
+        from_color: color,
+        to_color: color,
+    });
+}
+
+fn push_circle(lines: &mut Vec<OverlayLine>, (x, y): (f64, f64), radius: f64, color: [u8; 3]) {
+    let point = |i: usize| {
+        let angle = i as f64 / CIRCLE_SEGMENTS as f64 * 2.0 * std::f64::consts::PI;
+        (
+            (x + radius * angle.cos()).round() as i32,
+            (y + radius * angle.sin()).round() as i32,
+        )
+    };
+
+    for i in 0..CIRCLE_SEGMENTS {
+        lines.push(OverlayLine {
+            from: point(i),
+            to: point(i + 1),
+            from_color: color,
+            to_color: color,
+        });
+    }
+}
+
+// Outline of a bound orbit, traced through the camera so its tilt shows
+fn push_orbit_outline(
+    lines: &mut Vec<OverlayLine>,
+    projection: &Projection,
+    parent_position: DVec3,
+    elements: &OrbitalElements,
+    color: [u8; 3],
+) {
+    let point = |i: usize| {
+        let anomaly = i as f64 / CIRCLE_SEGMENTS as f64 * 2.0 * std::f64::consts::PI;
+        projection
+            .point(parent_position + elements.position_at(anomaly))
+            .map(|(x, y, _)| (x.round() as i32, y.round() as i32))
+    };
+
+    for i in 0..CIRCLE_SEGMENTS {
+        if let (Some(from), Some(to)) = (point(i), point(i + 1)) {
+            lines.push(OverlayLine {
+                from,
+                to,
+                from_color: color,
+                to_color: color,
+            });
+        }
+    }
+}
+
+// Ring around the camera target with ticks pointing at it, sized relative to the drawn body
+fn get_reticle_lines(target: &TargetData) -> Vec<OverlayLine> {
+    let ring_radius = target.radius * RETICLE_SCALE + RETICLE_GAP;
+    let tick_length = ring_radius * RETICLE_TICK_LENGTH;
+
+    let mut lines = vec![];
+    push_circle(&mut lines, (target.x, target.y), ring_radius, RETICLE_COLOR);
+
+    for (dx, dy) in [(1.0, 0.0), (-1.0, 0.0), (0.0, 1.0), (0.0, -1.0)] {
+        let point = |distance: f64| {
+            (
+                (target.x + dx * distance).round() as i32,
+                (target.y + dy * distance).round() as i32,
+            )
+        };
+        lines.push(OverlayLine {
+            from: point(ring_radius),
+            to: point(ring_radius + tick_length),
+            from_color: RETICLE_COLOR,
+            to_color: RETICLE_COLOR,
+        });
+    }
+
+    lines
+}
+
+const RETICLE_SCALE: f64 = 1.3;
+const RETICLE_GAP: f64 = 4.0; // Pixels between a tiny body and the ring
+const RETICLE_TICK_LENGTH: f64 = 0.5; // Relative to the ring radius
+const RETICLE_COLOR: [u8; 3] = [0, 255, 128];
+
+// Left behind at each perihelion passage. Stored relative to the parent so the pattern moves along with it
+pub struct PerihelionMark {
+    pub parent: Uuid,
+    pub offset: DVec3,
+    pub color: [u8; 3],
+}
+
+const MAX_PERIHELION_MARKS: usize = 10000;
+
+// Finds perihelion passages once per published snapshot, so the frame rate doesn't matter
+#[derive(Default)]
+pub struct PerihelionTracker {
+    pub marks: Vec<PerihelionMark>,
+    parents: HashMap<Uuid, Uuid>, // Finding orbital parents is O(n^2), so they are only refreshed with the status text
+    radial_speeds: HashMap<Uuid, (Uuid, f64)>, // Parent and radial speed in the previous snapshot
+    last_snapshot: Option<Arc<Vec<AstronomicalObject>>>,
+}
+
+impl PerihelionTracker {
+    pub fn clear(&mut self) {
+        *self = PerihelionTracker::default();
+    }
+
+    pub fn refresh_parents(&mut self, bodies: &[AstronomicalObject]) {
+        self.parents = bodies
+            .iter()
+            .filter_map(|x| {
+                engine::Engine::find_orbital_parent(x, bodies).map(|p| (x.uuid, p.uuid))
+            })
+            .collect();
+    }
+
+    pub fn update(&mut self, bodies: &Arc<Vec<AstronomicalObject>>) {
+        if self
+            .last_snapshot
+            .as_ref()
+            .is_some_and(|x| Arc::ptr_eq(x, bodies))
+        {
+            return;
+        }
+        self.last_snapshot = Some(bodies.clone());
+
+        let by_uuid: HashMap<Uuid, &AstronomicalObject> =
+            bodies.iter().map(|x| (x.uuid, x)).collect();
+
+        for body in bodies.iter() {
+            let parent = match self.parents.get(&body.uuid).and_then(|p| by_uuid.get(p)) {
+                Some(p) => p,
+                None => continue,
+            };
+
+            let offset = body.position - parent.position;
+            let radial_speed = offset.dot(body.velocity - parent.velocity);
+
+            // Distance to the same parent stops decreasing and starts increasing
+            if let Some((previous_parent, previous)) = self
+                .radial_speeds
+                .insert(body.uuid, (parent.uuid, radial_speed))
+            {
+                if previous_parent == parent.uuid && previous < 0.0 && radial_speed >= 0.0 {
+                    self.marks.push(PerihelionMark {
+                        parent: parent.uuid,
+                        offset,
+                        color: blend_color(body.color, [0, 0, 0], 0.5),
+                    });
+                }
+            }
+        }
+
+        if self.marks.len() > MAX_PERIHELION_MARKS {
+            let excess = self.marks.len() - MAX_PERIHELION_MARKS;
+            self.marks.drain(..excess);
+        }
+    }
+}
+
 pub struct TargetData {
     pub uuid: Uuid,
     pub x: f64,
@@ -18,7 +434,38 @@ pub struct TargetData {
     pub radius: f64,
 }
 
+unsafe fn draw_overlay_lines(app: &DrawingApp, dc: HDC, lines: &[OverlayLine]) {
+    for line in lines.iter() {
+        let segments = if line.from_color == line.to_color {
+            1
+        } else {
+            GRADIENT_SEGMENTS
+        };
+
+        for k in 0..segments {
+            let (t0, t1) = (k as f64 / segments as f64, (k + 1) as f64 / segments as f64);
+            let [r, g, b] = blend_color(line.from_color, line.to_color, (t0 + t1) / 2.0);
+            let lerp = |a: i32, b: i32, t: f64| (a as f64 + (b - a) as f64 * t).round() as i32;
+
+            SelectObject(dc, app.get_pen(r, g, b) as _);
+            MoveToEx(
+                dc,
+                lerp(line.from.0, line.to.0, t0),
+                lerp(line.from.1, line.to.1, t0),
+                std::ptr::null_mut(),
+            );
+            LineTo(
+                dc,
+                lerp(line.from.0, line.to.0, t1),
+                lerp(line.from.1, line.to.1, t1),
+            );
+        }
+    }
+}
+
 pub fn paint(app: &DrawingApp, data: &nwg::EventData) {
+    handle_simulation_events(app);
+
     let paint_objects = get_paint_objects(app);
     let now = Instant::now();
 
@@ -28,6 +475,13 @@ pub fn paint(app: &DrawingApp, data: &nwg::EventData) {
     if *app.next_status_update.borrow() <= now {
         *status_lines = get_status_text(app);
         *object_description = get_object_description_text(app);
+        object_description.extend(get_measurement_text(app));
+        *app.hierarchy_lines.borrow_mut() = get_hierarchy_text(app);
+        if app.render_options.borrow().perihelion_marks {
+            app.perihelia
+                .borrow_mut()
+                .refresh_parents(&app.engine.snapshot());
+        }
         *app.next_status_update.borrow_mut() = Instant::now() + Duration::from_millis(500);
     }
 
@@ -49,9 +503,133 @@ pub fn paint(app: &DrawingApp, data: &nwg::EventData) {
         FillRect(mem_dc, rc, p.background as _);
         SelectObject(mem_dc, p.pen as _);
 
-        for (left_x, right_x, top_y, bottom_y, brush) in paint_objects.iter() {
-            SelectObject(mem_dc, *brush as _);
-            Ellipse(mem_dc, *left_x, *top_y, *right_x, *bottom_y);
+        if app.render_options.borrow().smooth_overlays {
+            // Overlays are drawn first onto the plain background, so the downscaled buffer can simply replace it
+            let ss_dc = CreateCompatibleDC(hdc);
+            let ss_bitmap = CreateCompatibleBitmap(
+                hdc,
+                rc.right * OVERLAY_SUPERSAMPLING,
+                rc.bottom * OVERLAY_SUPERSAMPLING,
+            );
+            let prev_ss_bitmap = SelectObject(ss_dc, ss_bitmap as _);
+
+            let ss_rect = RECT {
+                left: 0,
+                top: 0,
+                right: rc.right * OVERLAY_SUPERSAMPLING,
+                bottom: rc.bottom * OVERLAY_SUPERSAMPLING,
+            };
+            FillRect(ss_dc, &ss_rect, p.background as _);
+
+            // World transform scales pens too, so lines stay one pixel wide after downscaling
+            SetGraphicsMode(ss_dc, GM_ADVANCED as i32);
+            let scale = XFORM {
+                eM11: OVERLAY_SUPERSAMPLING as f32,
+                eM12: 0.0,
+                eM21: 0.0,
+                eM22: OVERLAY_SUPERSAMPLING as f32,
+                eDx: 0.0,
+                eDy: 0.0,
+            };
+            SetWorldTransform(ss_dc, &scale);
+
+            draw_overlay_lines(app, ss_dc, &app.overlay_lines.borrow());
+
+            SetStretchBltMode(mem_dc, HALFTONE);
+            SetBrushOrgEx(mem_dc, 0, 0, std::ptr::null_mut());
+            StretchBlt(
+                mem_dc,
+                0,
+                0,
+                rc.right,
+                rc.bottom,
+                ss_dc,
+                0,
+                0,
+                ss_rect.right,
+                ss_rect.bottom,
+                SRCCOPY,
+            );
+
+            SelectObject(ss_dc, prev_ss_bitmap);
+            DeleteObject(ss_bitmap as _);
+            DeleteDC(ss_dc);
+        } else {
+            draw_overlay_lines(app, mem_dc, &app.overlay_lines.borrow());
+        }
+        SelectObject(mem_dc, p.pen as _);
+
+        // Textures are selected into their own DC for blitting
+        let texture_dc = CreateCompatibleDC(mem_dc);
+        let mut prev_texture: HGDIOBJ = std::ptr::null_mut();
+        SetStretchBltMode(mem_dc, HALFTONE);
+
+        for (left_x, right_x, top_y, bottom_y, brush, texture, shape) in paint_objects.iter() {
+            let (center_x, center_y) = ((left_x + right_x) / 2, (top_y + bottom_y) / 2);
+
+            match (shape, texture) {
+                (RenderShape::Point, _) => {
+                    let pixel = RECT {
+                        left: center_x,
+                        top: center_y,
+                        right: center_x + 1,
+                        bottom: center_y + 1,
+                    };
+                    FillRect(mem_dc, &pixel, *brush);
+                }
+                (RenderShape::Cross, _) => {
+                    let horizontal = RECT {
+                        left: center_x - CROSS_SIZE,
+                        top: center_y,
+                        right: center_x + CROSS_SIZE + 1,
+                        bottom: center_y + 1,
+                    };
+                    let vertical = RECT {
+                        left: center_x,
+                        top: center_y - CROSS_SIZE,
+                        right: center_x + 1,
+                        bottom: center_y + CROSS_SIZE + 1,
+                    };
+                    FillRect(mem_dc, &horizontal, *brush);
+                    FillRect(mem_dc, &vertical, *brush);
+                }
+                (RenderShape::Circle, Some((bitmap, width, height))) => {
+                    let prev = SelectObject(texture_dc, *bitmap as _);
+                    if prev_texture.is_null() {
+                        prev_texture = prev;
+                    }
+                    StretchBlt(
+                        mem_dc,
+                        *left_x,
+                        *top_y,
+                        right_x - left_x,
+                        bottom_y - top_y,
+                        texture_dc,
+                        0,
+                        0,
+                        *width,
+                        *height,
+                        SRCCOPY,
+                    );
+                }
+                (RenderShape::Circle, None) => {
+                    SelectObject(mem_dc, *brush as _);
+                    Ellipse(mem_dc, *left_x, *top_y, *right_x, *bottom_y);
+                }
+            }
+        }
+
+        if !prev_texture.is_null() {
+            SelectObject(texture_dc, prev_texture);
+        }
+        DeleteDC(texture_dc);
+
+        // Drawn over the bodies so it stays visible in crowded views
+        if app.render_options.borrow().target_reticle {
+            let target = *app.current_target.borrow();
+            if let Some(t) = app.targets.borrow().iter().find(|x| Some(x.uuid) == target) {
+                draw_overlay_lines(app, mem_dc, &get_reticle_lines(t));
+            }
         }
 
         FrameRect(mem_dc, rc, p.border as _);
@@ -62,7 +640,7 @@ pub fn paint(app: &DrawingApp, data: &nwg::EventData) {
         let line_height = 18;
         let text_start_y = size.1 as i32 - status_lines.len() as i32 * line_height - 5;
 
-        let use_target_speed = app.engine.params.lock().unwrap().use_target_speed;
+        let use_target_speed = app.engine.params.lock_recover().use_target_speed;
         for (i, text_str) in status_lines.iter().enumerate() {
             let text = text_str.encode_utf16().collect::<Vec<u16>>();
             if use_target_speed && i == 0 || !use_target_speed && i == 1 {
@@ -80,6 +658,11 @@ pub fn paint(app: &DrawingApp, data: &nwg::EventData) {
         }
 
         SelectObject(mem_dc, p.font as _);
+        for label in app.overlay_labels.borrow().iter() {
+            let text = label.text.encode_utf16().collect::<Vec<u16>>();
+            TextOutW(mem_dc, label.x, label.y, text.as_ptr(), text.len() as i32);
+        }
+
         for (i, text_str) in object_description.iter().enumerate() {
             let text = text_str.encode_utf16().collect::<Vec<u16>>();
             TextOutW(
@@ -91,6 +674,18 @@ pub fn paint(app: &DrawingApp, data: &nwg::EventData) {
             );
         }
 
+        // Below the speed input
+        for (i, text_str) in app.hierarchy_lines.borrow().iter().enumerate() {
+            let text = text_str.encode_utf16().collect::<Vec<u16>>();
+            TextOutW(
+                mem_dc,
+                size.0 as i32 - 190,
+                45 + i as i32 * 18,
+                text.as_ptr(),
+                text.len() as i32,
+            );
+        }
+
         BitBlt(hdc, 0, 0, rc.right, rc.bottom, mem_dc, 0, 0, SRCCOPY);
 
         SelectObject(mem_dc, prev_bitmap);
@@ -101,24 +696,150 @@ pub fn paint(app: &DrawingApp, data: &nwg::EventData) {
     paint.end_paint(&ps);
 }
 
-pub fn get_status_text(app: &DrawingApp) -> Vec<String> {
-    let objects_len = app.engine.objects.lock().unwrap().len();
-    let params = app.engine.params.lock().unwrap().clone();
+fn handle_simulation_events(app: &DrawingApp) {
+    for event in app.simulation_events.try_iter() {
+        match event {
+            SimulationEvent::Collision {
+                time,
+                survivor,
+                survivor_name,
+                absorbed,
+                absorbed_name,
+            } => {
+                println!("New event at {:.2} y:", time / (3600.0 * 24.0 * 365.0));
+                println!("{} collided into {}!", absorbed_name, survivor_name);
 
-    let method = match params.method {
-        IntegrationMethod::Symplectic(k) => {
-            format!(
-                "Symplectic - {} order",
-                match k {
-                    1 => "1st",
-                    2 => "2nd",
-                    3 => "3rd",
-                    4 => "4th",
-                    _ => "??",
+                // Keep following the merged body if the current target got absorbed
+                let mut current_target = app.current_target.borrow_mut();
+                if *current_target == Some(absorbed) {
+                    *current_target = Some(survivor);
                 }
-            )
+
+                let mut focus_group = app.focus_group.borrow_mut();
+                if let Some(index) = focus_group.iter().position(|x| *x == absorbed) {
+                    focus_group.remove(index);
+                    if !focus_group.contains(&survivor) {
+                        focus_group.push(survivor);
+                    }
+                }
+            }
+            SimulationEvent::BodyAdded { uuid, name } => {
+                println!("{} added ({})", name, uuid);
+            }
+            SimulationEvent::Depleted { time, uuid, name } => {
+                println!(
+                    "{} lost all of its mass at {:.2} y",
+                    name,
+                    time / (3600.0 * 24.0 * 365.0)
+                );
+
+                let mut current_target = app.current_target.borrow_mut();
+                if *current_target == Some(uuid) {
+                    *current_target = None;
+                }
+                app.focus_group.borrow_mut().retain(|x| *x != uuid);
+            }
+            SimulationEvent::EnergyDrift {
+                time,
+                drift,
+                policy,
+            } => {
+                println!(
+                    "WARNING: total energy drifted by {:.2e} at {:.2} y ({:?})",
+                    drift,
+                    time / (3600.0 * 24.0 * 365.0),
+                    policy
+                );
+            }
+            SimulationEvent::Stopped { time } => {
+                println!(
+                    "Simulation stopped at {:.2} y",
+                    time / (3600.0 * 24.0 * 365.0)
+                );
+            }
         }
+
+        *app.next_status_update.borrow_mut() = Instant::now();
+    }
+}
+
+fn format_distance(distance: f64) -> String {
+    if distance == 0.0 {
+        "Off".into()
+    } else if distance >= 0.01 * AU {
+        format!("{:.3} AU", distance / AU)
+    } else {
+        format!("{:.1} km", distance / 1000.0)
+    }
+}
+
+// E.g. 3.2 million
+fn format_large_number(value: f64) -> String {
+    let names = [
+        (1.0E12, "trillion"),
+        (1.0E9, "billion"),
+        (1.0E6, "million"),
+        (1.0E3, "thousand"),
+    ];
+
+    for (scale, name) in names {
+        if value >= scale {
+            return format!("{:.1} {}", value / scale, name);
+        }
+    }
+
+    format!("{:.1}", value)
+}
+
+pub fn get_status_text(app: &DrawingApp) -> Vec<String> {
+    let params = app.engine.params.lock_recover().clone();
+    let (objects_len, virial_ratio, extent) = {
+        let objects = app.engine.snapshot();
+
+        // Kinetic energy in the center of mass frame
+        let total_mass: f64 = objects.iter().map(|x| x.mass).sum();
+        let (_, com_velocity) = engine::Engine::get_center_of_mass(&objects);
+        let kinetic = integration::kinetic_energy(&objects)
+            - 0.5 * total_mass * com_velocity.length_squared();
+
+        (
+            objects.len(),
+            2.0 * kinetic / integration::potential_energy(&objects, params.softening).abs(),
+            engine::Engine::get_bounding_sphere(&objects).1,
+        )
+    };
+    let logging_elements = app.engine.element_log.lock_recover().is_some();
+    let (camera_smoothing, camera_aligned, camera_follow_velocity) = {
+        let camera = app.camera.lock_recover();
+        (
+            camera.smoothing,
+            camera.align_to_angular_momentum,
+            camera.follow_velocity,
+        )
+    };
+    let render_options = app.render_options.borrow();
+
+    let order = |k: u8| match k {
+        1 => "1st",
+        2 => "2nd",
+        3 => "3rd",
+        4 => "4th",
+        _ => "??",
+    };
+    let method = match params.method {
+        IntegrationMethod::Symplectic(k) => format!("Symplectic - {} order", order(k)),
         IntegrationMethod::RK4 => "Runge-Kutta 4".into(),
+        IntegrationMethod::Kepler => "Kepler (exact for two bodies)".into(),
+        IntegrationMethod::BarnesHut { theta, order: k } => {
+            format!("Barnes-Hut θ = {} - {} order", theta, order(k))
+        }
+    };
+    let method = match (&params.method, params.body_order_count()) {
+        (_, 0) => method,
+        (IntegrationMethod::Symplectic(_), n) => {
+            format!("{}, {} bodies with their own order", method, n)
+        }
+        (_, n) => format!("{}, {} body orders ignored", method, n),
     };
 
     let lines = vec![
@@ -152,18 +873,270 @@ pub fn get_status_text(app: &DrawingApp) -> Vec<String> {
             "Simulation time: {:.2} y",
             params.time_elapsed / (60.0 * 60.0 * 24.0 * 365.0)
         ),
+        format!(
+            "Stop at: {}",
+            match (params.stop_at_time, params.stop_at_step) {
+                (None, None) => "-".into(),
+                (Some(t), None) => format!("{:.2} y", t / (60.0 * 60.0 * 24.0 * 365.0)),
+                (None, Some(n)) => format!("step {} (now {})", n, params.steps_elapsed),
+                (Some(t), Some(n)) => format!(
+                    "{:.2} y or step {} (now {})",
+                    t / (60.0 * 60.0 * 24.0 * 365.0),
+                    n,
+                    params.steps_elapsed
+                ),
+            }
+        ),
+        format!(
+            "Time compression: {} × real time",
+            format_large_number(params.time_step * params.iteration_speed)
+        ),
         format!("Objects: {}", objects_len),
+        format!("Virial ratio: {:.3}", virial_ratio),
+        format!("System extent: {:.2} AU (radius)", extent / AU),
+        format!(
+            "Cloud seed: {}",
+            match *app.cloud_seed.borrow() {
+                Some(seed) => seed.to_string(),
+                None => "-".into(),
+            }
+        ),
+        format!(
+            "Focus: {}",
+            match app.focus_group.borrow().len() {
+                0 => "selected body".into(),
+                n => format!("barycenter of {} bodies", n),
+            }
+        ),
+        format!(
+            "Element log: {}",
+            match logging_elements {
+                true => "On",
+                false => "Off",
+            }
+        ),
         format!("Method: {}", method),
-        format!("Threads: {}", params.num_threads),
+        format!(
+            "Accuracy: {}",
+            match params.accuracy {
+                Accuracy::Manual => "manual".into(),
+                accuracy => format!("{:?} (step x{:.4})", accuracy, params.step_fraction),
+            }
+        ),
+        format!(
+            "Collision slowdown: {}",
+            match params.collision_slowdown {
+                false => "Off".into(),
+                true => format!("x{:.2}", params.slowdown_factor),
+            }
+        ),
+        format!(
+            "Energy watchdog: {}",
+            match params.watchdog_policy {
+                WatchdogPolicy::Off => "Off".into(),
+                WatchdogPolicy::Warn => format!("warn above {:.0e}", params.max_energy_drift),
+                WatchdogPolicy::Pause => format!("pause above {:.0e}", params.max_energy_drift),
+                WatchdogPolicy::ReduceTimestep =>
+                    format!("reduce step above {:.0e}", params.max_energy_drift),
+            }
+        ),
+        format!("Softening: {}", format_distance(params.softening)),
+        format!(
+            "External field: {}",
+            match params.external_field {
+                ExternalField::Off => "Off".into(),
+                ExternalField::Uniform(a) => format!("uniform {:.2e} m/s^2", a.length()),
+                ExternalField::Oscillating { amplitude, period } => format!(
+                    "oscillating {:.2e} m/s^2, period {:.2e} s",
+                    amplitude.length(),
+                    period
+                ),
+                ExternalField::Tidal(frequency) => format!("tidal {:.2e} rad/s", frequency),
+            }
+        ),
+        format!(
+            "Scenery gravity: {}",
+            match params.scenery_gravity {
+                true => "On",
+                false => "Off",
+            }
+        ),
+        format!(
+            "Merge colors: {}",
+            match params.blend_merge_colors {
+                true => "Blend",
+                false => "Keep heavier",
+            }
+        ),
+        format!(
+            "Boundary: {}",
+            match params.boundary {
+                Boundary::Off => "off".into(),
+                Boundary::Periodic(half_size) => format!("periodic ±{:.0} AU", half_size / AU),
+                Boundary::Reflect(half_size) => format!("reflecting ±{:.0} AU", half_size / AU),
+            }
+        ),
+        format!(
+            "Threads: {} ({} chunks each)",
+            params.num_threads, params.chunks_per_thread
+        ),
+        format!(
+            "Collision order: {}",
+            match params.ordered_collisions {
+                true => "Deterministic",
+                false => "First found",
+            }
+        ),
         format!("Speed: {:.0} n/s", params.iteration_speed),
+        format!("Camera smoothing: {:.2}", camera_smoothing),
+        format!(
+            "Camera up: {}",
+            match camera_aligned {
+                true => "Angular momentum",
+                false => "World Y",
+            }
+        ),
+        format!(
+            "Follow velocity: {}",
+            match camera_follow_velocity {
+                true => "On",
+                false => "Off",
+            }
+        ),
+        format!(
+            "Doppler tint: {}",
+            if render_options.doppler { "on" } else { "off" }
+        ),
+        format!(
+            "Sun lighting: {}",
+            match render_options.sun_lighting {
+                true => "On",
+                false => "Off",
+            }
+        ),
+        format!(
+            "Radial scale: {}",
+            if render_options.log_radial_scale {
+                "logarithmic (non-physical)"
+            } else {
+                "linear"
+            }
+        ),
+        format!(
+            "Limiting magnitude: {}",
+            match render_options.limiting_magnitude {
+                Some(m) => format!("{:.0}", m),
+                None => "none".into(),
+            }
+        ),
+        format!(
+            "Showing: {}",
+            match render_options.bound_filter {
+                BoundFilter::All => "all bodies",
+                BoundFilter::BoundOnly => "bound bodies",
+                BoundFilter::UnboundOnly => "unbound bodies",
+            }
+        ),
+        format!(
+            "Body shape: {}",
+            match render_options.render_shape {
+                RenderShape::Circle => "circle",
+                RenderShape::Cross => "cross",
+                RenderShape::Point => "point",
+            }
+        ),
+        format!(
+            "Smooth overlays: {}",
+            match render_options.smooth_overlays {
+                true => "On",
+                false => "Off",
+            }
+        ),
+        format!(
+            "Depth cueing: {}",
+            match render_options.depth_cueing {
+                true => format!("gamma {:.1}", render_options.depth_cue_gamma),
+                false => "Off".into(),
+            }
+        ),
+        format!(
+            "Display precision: {} digits",
+            render_options.display_precision + 1
+        ),
+        format!(
+            "Apsis markers: {}",
+            match render_options.apsis_markers {
+                true => "On",
+                false => "Off",
+            }
+        ),
+        format!(
+            "Target reticle: {}",
+            match render_options.target_reticle {
+                true => "On",
+                false => "Off",
+            }
+        ),
+        format!(
+            "Orbit planes: {}",
+            match render_options.orbit_planes {
+                OrbitPlaneDisplay::Off => "Off",
+                OrbitPlaneDisplay::Selected => "Selected body",
+                OrbitPlaneDisplay::All => "All bound bodies",
+            }
+        ),
+        format!(
+            "Roche limits: {}",
+            match render_options.roche_limits {
+                RocheDisplay::Off => "Off",
+                RocheDisplay::Selected => "Selected body",
+                RocheDisplay::Massive => "Massive bodies",
+            }
+        ),
     ];
 
     lines
 }
 
+pub fn get_hierarchy_text(app: &DrawingApp) -> Vec<String> {
+    if !app.render_options.borrow().hierarchy {
+        return vec![];
+    }
+
+    fn push_lines(
+        node: &HierarchyNode,
+        depth: usize,
+        target: Option<Uuid>,
+        lines: &mut Vec<String>,
+    ) {
+        let marker = if Some(node.uuid) == target { "> " } else { "" };
+        lines.push(format!("{}{}{}", "    ".repeat(depth), marker, node.name));
+        for child in node.children.iter() {
+            push_lines(child, depth + 1, target, lines);
+        }
+    }
+
+    let hierarchy = engine::Engine::get_hierarchy(&app.engine.snapshot());
+    let target = *app.current_target.borrow();
+
+    let mut lines = vec![];
+    for root in hierarchy.iter() {
+        push_lines(root, 0, target, &mut lines);
+    }
+
+    if lines.len() > HIERARCHY_MAX_LINES {
+        let hidden = lines.len() - HIERARCHY_MAX_LINES;
+        lines.truncate(HIERARCHY_MAX_LINES);
+        lines.push(format!("... {} more", hidden));
+    }
+
+    lines
+}
+
 pub fn get_object_description_text(app: &DrawingApp) -> Vec<String> {
+    let precision = app.render_options.borrow().display_precision;
     let obj;
-    let objects = app.engine.objects.lock().unwrap().clone();
+    let objects = app.engine.snapshot();
     if let Some(target) = *app.current_target.borrow() {
         if let Some(object) = objects.iter().find(|x| x.uuid == target) {
             obj = object;
@@ -178,67 +1151,378 @@ pub fn get_object_description_text(app: &DrawingApp) -> Vec<String> {
     if let Some(parent) = engine::Engine::find_orbital_parent(obj, &objects) {
         parent_info = vec![
             format!(
-                " - {:.4e} m/s compared to {}",
+                " - {:.precision$e} m/s compared to {}",
                 (obj.velocity - parent.velocity).length(),
                 parent.name
             ),
             format!(
-                " - {:.4e} m/s^2 compared to {}",
+                " - {:.precision$e} m/s^2 compared to {}",
                 (obj.acceleration - parent.acceleration).length(),
                 parent.name
             ),
             format!(
-                " - {:.4e} J compared to {}",
+                " - {:.precision$e} J compared to {}",
                 0.5 * (obj.velocity - parent.velocity).length_squared() * obj.mass,
                 parent.name
             ),
         ]
     }
 
+    let nearest_neighbor = objects
+        .iter()
+        .filter(|x| x.uuid != obj.uuid)
+        .map(|x| (x, x.position.distance(obj.position)))
+        .min_by(|a, b| a.1.total_cmp(&b.1));
+
+    let nearest_neighbor_info = match nearest_neighbor {
+        Some((neighbor, distance)) => format!("{:.precision$e} m ({})", distance, neighbor.name),
+        None => "-".into(),
+    };
+
+    let kinetic_energy = 0.5 * obj.mass * obj.velocity.length_squared();
+    let softening = app.engine.params.lock_recover().softening;
+    let potential_energy = integration::body_potential_energy(obj, &objects, softening);
+
+    let body_order = app.engine.params.lock_recover().body_order(obj.uuid);
+
     vec![
         format!("Name: {}", obj.name),
-        format!("Mass: {:.4e} kg", obj.mass),
-        format!("Radius: {:.4e} m", obj.radius),
-        format!("Speed: {:.4e} m/s{}", obj.velocity.length(), parent_info[0]),
+        format!("Mass: {:.precision$e} kg", obj.mass),
+        format!(
+            "Motion: {}",
+            match obj.scenery_parent {
+                Some(_) => "Kepler orbit (scenery)",
+                None => "integrated",
+            }
+        ),
+        format!(
+            "Symplectic order: {}",
+            match body_order {
+                Some(k) => k.to_string(),
+                None => "method".into(),
+            }
+        ),
+        format!("Radius: {:.precision$e} m", obj.radius),
+        format!(
+            "Speed: {:.precision$e} m/s{}",
+            obj.velocity.length(),
+            parent_info[0]
+        ),
         format!(
-            "Acceleration magnitude: {:.4e} m/s^2{}",
+            "Acceleration magnitude: {:.precision$e} m/s^2{}",
             obj.acceleration.length(),
             parent_info[1]
         ),
         format!(
-            "Kinetic energy: {:.4e} J{}",
-            0.5 * obj.mass * obj.velocity.length_squared(),
-            parent_info[2]
+            "Kinetic energy: {:.precision$e} J{}",
+            kinetic_energy, parent_info[2]
+        ),
+        format!("Potential energy: {:.precision$e} J", potential_energy),
+        format!(
+            "Total energy: {:.precision$e} J ({})",
+            kinetic_energy + potential_energy,
+            match kinetic_energy + potential_energy < 0.0 {
+                true => "bound",
+                false => "unbound",
+            }
         ),
+        format!("Nearest neighbor: {}", nearest_neighbor_info),
         "".into(),
         format!(
-            "Position: [{:.4e}, {:.4e}, {:.4e}]",
+            "Position: [{:.precision$e}, {:.precision$e}, {:.precision$e}]",
             obj.position.x, obj.position.y, obj.position.z
         ),
         format!(
-            "Velocity: [{:.4e}, {:.4e}, {:.4e}]",
+            "Velocity: [{:.precision$e}, {:.precision$e}, {:.precision$e}]",
             obj.velocity.x, obj.velocity.y, obj.velocity.z
         ),
         format!(
-            "Acceleration: [{:.4e}, {:.4e}, {:.4e}]",
+            "Acceleration: [{:.precision$e}, {:.precision$e}, {:.precision$e}]",
             obj.acceleration.x, obj.acceleration.y, obj.acceleration.z
         ),
     ]
 }
 
-pub fn get_paint_objects(app: &DrawingApp) -> Vec<(i32, i32, i32, i32, HBRUSH)> {
-    let bodies = app.engine.objects.lock().unwrap().clone();
-    let mut camera = app.camera.lock().unwrap();
+pub fn get_measurement_text(app: &DrawingApp) -> Vec<String> {
+    let precision = app.render_options.borrow().display_precision;
+    let measurement = app.measurement.borrow();
+    let picked = match &*measurement {
+        Some(picked) => picked,
+        None => return vec![],
+    };
+
+    let objects = app.engine.snapshot();
+    let bodies: Vec<_> = picked
+        .iter()
+        .filter_map(|uuid| objects.iter().find(|x| x.uuid == *uuid))
+        .collect();
+
+    let mut lines = vec!["".into(), "Measurement (click two bodies):".into()];
+
+    if let [a, b] = bodies[..] {
+        let relative_velocity = b.velocity - a.velocity;
+        let reduced_mass = a.mass * b.mass / (a.mass + b.mass);
+
+        lines.extend([
+            format!(" - {} <-> {}", a.name, b.name),
+            format!(
+                " - Separation: {:.precision$e} m",
+                a.position.distance(b.position)
+            ),
+            format!(
+                " - Relative speed: {:.precision$e} m/s",
+                relative_velocity.length()
+            ),
+            format!(
+                " - Relative kinetic energy: {:.precision$e} J",
+                0.5 * reduced_mass * relative_velocity.length_squared()
+            ),
+        ]);
+    } else {
+        bodies
+            .iter()
+            .for_each(|x| lines.push(format!(" - {}", x.name)));
+    }
+
+    lines
+}
+
+// Camera projection that doesn't depend on the window or GDI state, so results are reproducible from bodies and a camera
+pub struct Projection {
+    transform: DAffine3,
+    target: DVec3,
+    distance: f64,
+    screen_scalar: f64,
+    screen_size: (u32, u32),
+    log_radial_scale: bool,
+    pub camera_position: DVec3,
+}
+
+impl Projection {
+    pub fn new(camera: &Camera, screen_size: (u32, u32), log_radial_scale: bool) -> Self {
+        Projection {
+            transform: camera.get_full_transformation(),
+            target: camera.target,
+            distance: camera.distance,
+            screen_scalar: screen_size.0 as f64 / 2.0 / (camera.fov / 2.0).to_radians().tan(),
+            screen_size,
+            log_radial_scale,
+            camera_position: camera.get_position(),
+        }
+    }
+
+    // Screen coordinates and perspective divisor of a point, None if it's behind the camera
+    pub fn point(&self, position: DVec3) -> Option<(f64, f64, f64)> {
+        let pos = if self.log_radial_scale {
+            self.transform.transform_point3(log_radial_position(
+                position,
+                self.target,
+                self.distance * LOG_SCALE_REFERENCE,
+            ))
+        } else {
+            self.transform.transform_point3(position)
+        };
+
+        if pos.z >= 1.0 {
+            return None;
+        }
+
+        let distance_scalar = 1.0 - pos.z;
+
+        // Transfer to screen coordinates
+        let x = pos.x / distance_scalar * self.screen_scalar + self.screen_size.0 as f64 / 2.0;
+        let y = self.screen_size.1 as f64 / 2.0 - pos.y / distance_scalar * self.screen_scalar;
+
+        Some((x, y, distance_scalar))
+    }
+
+    // Length in pixels of a distance perpendicular to the view at the given depth
+    pub fn scale_length(&self, length: f64, distance_scalar: f64) -> f64 {
+        length / self.distance / distance_scalar * self.screen_scalar
+    }
+
+    // Drawn radius in pixels. Small bodies are magnified so they stay visible
+    pub fn body_radius(&self, body: &AstronomicalObject, distance_scalar: f64) -> f64 {
+        let radius_without_mag = self.scale_length(body.radius, distance_scalar);
+        let radius_with_mag = radius_without_mag * body.magnification.powf(3.0_f64.recip());
+
+        let max_magnification = 10.0;
+        let radius = if radius_without_mag < max_magnification {
+            radius_with_mag.min(max_magnification)
+        } else {
+            radius_without_mag
+        };
+
+        radius.max(3.0)
+    }
+}
+
+pub struct ProjectedBody {
+    pub index: usize, // Index into the projected slice
+    pub x: f64,
+    pub y: f64,
+    pub radius: f64,
+    pub color: [u8; 3], // With doppler shift, lighting and depth cueing applied
+}
+
+impl ProjectedBody {
+    // Left, right, top and bottom edges in whole pixels
+    pub fn bounds(&self) -> (i32, i32, i32, i32) {
+        (
+            (self.x - self.radius).round() as i32,
+            (self.x + self.radius).round() as i32,
+            (self.y - self.radius).round() as i32,
+            (self.y + self.radius).round() as i32,
+        )
+    }
+}
+
+// Bodies that get drawn, ordered from back to front. Filtered, culled and colored by the display options
+pub fn project_bodies(
+    bodies: &[AstronomicalObject],
+    projection: &Projection,
+    render_options: &RenderOptions,
+) -> Vec<ProjectedBody> {
+    let camera_position = projection.camera_position;
+    let central_body = bodies.iter().max_by(|a, b| a.mass.total_cmp(&b.mass));
+
+    let mut sorted_indices: Vec<usize> = (0..bodies.len()).collect();
+    sorted_indices.sort_by(|a, b| bodies[*b].cmp(&bodies[*a], camera_position));
+
+    // Depth cueing ranks every body in front of the camera, drawn or not
+    let in_front: Vec<(usize, f64, f64, f64)> = sorted_indices
+        .into_iter()
+        .filter_map(|index| {
+            let (x, y, distance_scalar) = projection.point(bodies[index].position)?;
+            Some((index, x, y, distance_scalar))
+        })
+        .collect();
+    let body_count = in_front.len();
+
+    in_front
+        .into_iter()
+        .enumerate()
+        .filter_map(|(depth_rank, (index, x, y, distance_scalar))| {
+            let body = &bodies[index];
+
+            if let Some(central) = central_body {
+                let is_bound =
+                    body.uuid == central.uuid || body.specific_orbital_energy(central) < 0.0;
+                match render_options.bound_filter {
+                    BoundFilter::BoundOnly if !is_bound => return None,
+                    BoundFilter::UnboundOnly if is_bound && body.uuid != central.uuid => {
+                        return None
+                    }
+                    _ => {}
+                }
+            }
+            if let (Some(limit), Some(central)) = (render_options.limiting_magnitude, central_body)
+            {
+                if body.uuid != central.uuid
+                    && get_apparent_magnitude(body, central, camera_position) > limit
+                {
+                    return None;
+                }
+            }
+
+            let projected = ProjectedBody {
+                index,
+                x,
+                y,
+                radius: projection.body_radius(body, distance_scalar),
+                color: body.color,
+            };
+
+            let (left, right, top, bottom) = projected.bounds();
+            if right < 0
+                || bottom < 0
+                || left > projection.screen_size.0 as i32
+                || top > projection.screen_size.1 as i32
+            {
+                return None;
+            }
+
+            let mut color = body.color;
+            if render_options.doppler {
+                // Positive when receding from the camera
+                let line_of_sight_speed = body
+                    .velocity
+                    .dot((body.position - camera_position).normalize_or_zero());
+                let shift = line_of_sight_speed / DOPPLER_FULL_SHIFT_SPEED;
+
+                color = if shift > 0.0 {
+                    blend_color(color, [255, 0, 0], shift)
+                } else {
+                    blend_color(color, [0, 0, 255], -shift)
+                };
+            }
+
+            if render_options.sun_lighting {
+                if let Some(central) = central_body.filter(|c| c.uuid != body.uuid) {
+                    let brightness = get_illumination(body, central).clamp(MIN_BRIGHTNESS, 1.0);
+                    color = scale_brightness(color, brightness);
+                }
+            }
+
+            if render_options.depth_cueing {
+                let brightness = get_depth_cue_brightness(
+                    depth_rank,
+                    body_count,
+                    render_options.depth_cue_gamma,
+                );
+                color = scale_brightness(color, brightness);
+            }
+
+            Some(ProjectedBody { color, ..projected })
+        })
+        .collect()
+}
+
+pub fn get_paint_objects(app: &DrawingApp) -> Vec<PaintObject> {
+    let bodies = app.engine.snapshot();
+    let mut camera = app.camera.lock_recover();
     let mut target_opt = app.current_target.borrow_mut();
 
     let (screen_width_pix, screen_height_pix) = app.window.size();
-    let screen_scalar = screen_width_pix as f64 / 2.0 / (camera.fov / 2.0).to_radians().tan();
 
-    if let Some(target) = *target_opt {
+    let group: Vec<AstronomicalObject> = app
+        .focus_group
+        .borrow()
+        .iter()
+        .filter_map(|uuid| bodies.iter().find(|x| x.uuid == *uuid))
+        .cloned()
+        .collect();
+
+    if !group.is_empty() {
+        let (center, _) = engine::Engine::get_center_of_mass(&group);
+
+        camera.target = match camera.animation_start {
+            Some(_) => {
+                let extent = group
+                    .iter()
+                    .map(|x| x.position.distance(center) + x.radius)
+                    .fold(0.0, f64::max);
+
+                match camera.interpolate_animation(center, extent * GROUP_FRAMING) {
+                    Some((target, distance)) => {
+                        camera.set_distance(distance);
+
+                        if target == center {
+                            camera.animation_start = None;
+                        }
+
+                        target
+                    }
+                    None => center,
+                }
+            }
+            None => center,
+        }
+    } else if let Some(target) = *target_opt {
         camera.target = match bodies.iter().find(|x| x.uuid == target) {
             Some(b) => match camera.get_animation_position(b.position, b.radius) {
                 Some((target, distance)) => {
-                    camera.distance = distance;
+                    camera.set_distance(distance);
 
                     if target == b.position {
                         camera.animation_start = None;
@@ -253,69 +1537,207 @@ pub fn get_paint_objects(app: &DrawingApp) -> Vec<(i32, i32, i32, i32, HBRUSH)>
                 DVec3::ZERO
             }
         }
+    } else if let Some((target, distance)) = camera.get_free_animation_position() {
+        camera.target = target;
+        camera.set_distance(distance);
+
+        if Some((target, distance)) == camera.animation_destination {
+            camera.animation_start = None;
+            camera.animation_destination = None;
+        }
+    }
+
+    if camera.align_to_angular_momentum {
+        camera.set_up_axis(engine::Engine::get_angular_momentum(&bodies));
+    } else {
+        camera.set_up_axis(DVec3::Y);
     }
 
-    let transform = camera.get_full_transformation();
+    if camera.follow_velocity {
+        if let Some(body) = target_opt.and_then(|t| bodies.iter().find(|x| x.uuid == t)) {
+            let velocity = match engine::Engine::find_orbital_parent(body, &bodies) {
+                Some(parent) => body.velocity - parent.velocity,
+                None => body.velocity,
+            };
+            let velocity = camera.get_alignment().transform_vector3(velocity);
 
-    let mut output: Vec<(i32, i32, i32, i32, HBRUSH)> = Vec::new();
+            // Rotate so that the horizontal part of the velocity points to the right side of the screen,
+            // and look down on a climbing body (up on a descending one) by its elevation angle so that the
+            // vertical part of the velocity turns partly into depth and the motion stays close to horizontal
+            let horizontal_speed = DVec3::new(velocity.x, 0.0, velocity.z).length();
+            if horizontal_speed != 0.0 {
+                camera.set_yaw((-velocity.z).atan2(velocity.x));
+            }
+            if horizontal_speed != 0.0 || velocity.y != 0.0 {
+                camera.set_pitch(-velocity.y.atan2(horizontal_speed));
+            }
+        }
+    }
 
-    let mut sorted_indices: Vec<usize> = (0..bodies.len()).collect();
-    sorted_indices.sort_by(|a, b| bodies[*b].cmp(&bodies[*a], camera.get_position()));
+    camera.update_auto_orbit();
+    camera.update_orientation();
+
+    let render_options = app.render_options.borrow();
+    let projection = Projection::new(
+        &camera,
+        (screen_width_pix, screen_height_pix),
+        render_options.log_radial_scale,
+    );
+    let mut output: Vec<PaintObject> = Vec::new();
 
     let mut targets = app.targets.borrow_mut();
     targets.clear();
 
-    for i in sorted_indices {
-        let body = &bodies[i];
-        let pos = transform.transform_point3(body.position);
+    let central_body = bodies.iter().max_by(|a, b| a.mass.total_cmp(&b.mass));
 
-        if pos.z >= 1.0 {
-            continue;
+    let mut overlay_lines = app.overlay_lines.borrow_mut();
+    overlay_lines.clear();
+    let mut overlay_labels = app.overlay_labels.borrow_mut();
+    overlay_labels.clear();
+
+    let project = |position: DVec3| projection.point(position);
+
+    if render_options.apsis_markers {
+        let target = target_opt.and_then(|t| bodies.iter().find(|x| x.uuid == t));
+        if let Some(body) = target {
+            if let Some(parent) = engine::Engine::find_orbital_parent(body, &bodies) {
+                let elements = body.get_orbital_elements(parent);
+
+                if elements.is_bound() {
+                    let direction = elements.periapsis_direction();
+                    let apsides = [
+                        ("Periapsis", elements.periapsis_distance(), direction),
+                        ("Apoapsis", elements.apoapsis_distance(), -direction),
+                    ];
+
+                    for (name, distance, direction) in apsides {
+                        if let Some((x, y, _)) = project(parent.position + direction * distance) {
+                            let point = (x.round() as i32, y.round() as i32);
+                            push_marker(&mut overlay_lines, point, [255, 200, 0]);
+                            overlay_labels.push(OverlayLabel {
+                                x: point.0 + MARKER_SIZE + 3,
+                                y: point.1 - 9,
+                                text: format!(
+                                    "{}: {:.*e} m",
+                                    name, render_options.display_precision, distance
+                                ),
+                            });
+                        }
+                    }
+                }
+            }
         }
+    }
 
-        let distance_scalar = 1.0 - pos.z;
+    if render_options.perihelion_marks {
+        let mut perihelia = app.perihelia.borrow_mut();
+        perihelia.update(&bodies);
 
-        // Transfer to screen coordinates
-        let center_x = pos.x / distance_scalar * screen_scalar + screen_width_pix as f64 / 2.0;
-        let center_y = screen_height_pix as f64 / 2.0 - pos.y / distance_scalar * screen_scalar;
+        let by_uuid: HashMap<Uuid, &AstronomicalObject> =
+            bodies.iter().map(|x| (x.uuid, x)).collect();
+        for mark in perihelia.marks.iter() {
+            let parent = match by_uuid.get(&mark.parent) {
+                Some(p) => p,
+                None => continue,
+            };
 
-        let radius_without_mag = body.radius / camera.distance / distance_scalar * screen_scalar;
-        let radius_with_mag = radius_without_mag * body.magnification.powf(3.0_f64.recip());
+            if let Some((x, y, _)) = project(parent.position + mark.offset) {
+                let point = (x.round() as i32, y.round() as i32);
+                overlay_lines.push(OverlayLine {
+                    from: point,
+                    to: (point.0 + 1, point.1),
+                    from_color: mark.color,
+                    to_color: mark.color,
+                });
+            }
+        }
+    }
 
-        let max_magnification = 10.0;
-        let mut radius = if radius_without_mag < max_magnification {
-            radius_with_mag.min(max_magnification)
-        } else {
-            radius_without_mag
-        };
-        radius = radius.max(3.0);
+    if render_options.orbit_planes != OrbitPlaneDisplay::Off {
+        for body in bodies.iter() {
+            if render_options.orbit_planes == OrbitPlaneDisplay::Selected
+                && Some(body.uuid) != *target_opt
+            {
+                continue;
+            }
 
-        let left_x = center_x - radius;
-        let right_x = center_x + radius;
+            if let Some(parent) = engine::Engine::find_orbital_parent(body, &bodies) {
+                let elements = body.get_orbital_elements(parent);
+                if elements.is_bound() {
+                    // Faint, so overlapping orbits don't drown out the bodies
+                    let color = blend_color(body.color, [0, 0, 0], 0.7);
+                    push_orbit_outline(
+                        &mut overlay_lines,
+                        &projection,
+                        parent.position,
+                        &elements,
+                        color,
+                    );
+                }
+            }
+        }
+    }
 
-        let top_y = center_y - radius;
-        let bottom_y = center_y + radius;
+    if render_options.roche_limits != RocheDisplay::Off {
+        let heaviest_mass = central_body.map_or(0.0, |c| c.mass);
 
-        let res_left_x: i32 = left_x.round() as i32;
-        let res_right_x: i32 = right_x.round() as i32;
-        let res_top_y: i32 = top_y.round() as i32;
-        let res_bottom_y: i32 = bottom_y.round() as i32;
+        for body in bodies.iter() {
+            let shown = match render_options.roche_limits {
+                RocheDisplay::Selected => Some(body.uuid) == *target_opt,
+                _ => body.mass >= heaviest_mass * ROCHE_MASSIVE_FRACTION,
+            };
+            if !shown {
+                continue;
+            }
 
-        if res_right_x < 0
-            || res_bottom_y < 0
-            || res_left_x > screen_width_pix as i32
-            || res_top_y > screen_height_pix as i32
-        {
-            continue;
+            if let Some((x, y, distance_scalar)) = project(body.position) {
+                let radius = projection
+                    .scale_length(body.roche_limit(ROCHE_SATELLITE_DENSITY), distance_scalar);
+
+                if (2.0..1.0E5).contains(&radius) {
+                    // Dimmed to stay behind the bodies visually
+                    let color = blend_color(body.color, [0, 0, 0], 0.6);
+                    push_circle(&mut overlay_lines, (x, y), radius, color);
+                }
+            }
+        }
+    }
+
+    for projected_body in project_bodies(&bodies, &projection, &render_options) {
+        let body = &bodies[projected_body.index];
+
+        let (center_x, center_y, radius) =
+            (projected_body.x, projected_body.y, projected_body.radius);
+
+        if render_options.comet_tails && body.is_comet {
+            if let Some(central) = central_body.filter(|c| c.uuid != body.uuid) {
+                let away_from_sun = body.position - central.position;
+                let length = (COMET_TAIL_LENGTH * AU * (AU / away_from_sun.length()))
+                    .min(MAX_COMET_TAIL_LENGTH);
+                let tail_end = body.position + away_from_sun.normalize() * length;
+
+                if let Some((end_x, end_y, _)) = project(tail_end) {
+                    overlay_lines.push(OverlayLine {
+                        from: (center_x.round() as i32, center_y.round() as i32),
+                        to: (end_x.round() as i32, end_y.round() as i32),
+                        from_color: blend_color(body.color, [255, 255, 255], 0.5),
+                        to_color: [0, 0, 0],
+                    });
+                }
+            }
         }
 
-        let [r, g, b] = body.color;
+        let (res_left_x, res_right_x, res_top_y, res_bottom_y) = projected_body.bounds();
+
+        let [r, g, b] = projected_body.color;
         output.push((
             res_left_x,
             res_right_x,
             res_top_y,
             res_bottom_y,
             app.get_brush(r, g, b),
+            body.texture.as_ref().and_then(|path| app.get_texture(path)),
+            render_options.render_shape,
         ));
         targets.push(TargetData {
             uuid: body.uuid,
@@ -327,3 +1749,95 @@ pub fn get_paint_objects(app: &DrawingApp) -> Vec<(i32, i32, i32, i32, HBRUSH)>
 
     output
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn default_system_projection() {
+        let bodies = AstronomicalObject::default(&mut StdRng::seed_from_u64(42));
+        let projection = Projection::new(&Camera::default(), (1280, 720), false);
+        let render_options = RenderOptions {
+            doppler: true,
+            sun_lighting: true,
+            depth_cueing: true,
+            limiting_magnitude: Some(14.0),
+            ..Default::default()
+        };
+
+        // Back to front. Uranus and the Mars system are off screen, Pluto and the space station too dim
+        let expected = [
+            ("Neptune", 495.192150, 358.220754, 3.000000, [2, 4, 7]),
+            ("Saturn", 513.474150, 342.354142, 7.075008, [13, 13, 12]),
+            ("Jupiter", 860.041592, 365.474781, 10.000000, [17, 15, 12]),
+            ("Moon", 720.583668, 360.059354, 3.000000, [145, 117, 117]),
+            ("Earth", 720.986148, 360.000000, 3.000000, [33, 0, 126]),
+            ("Sun", 640.000000, 360.000000, 8.231893, [186, 186, 0]),
+            ("Mercury", 715.295044, 363.627025, 3.000000, [200, 0, 0]),
+            ("Venus", 750.945263, 378.962399, 6.429311, [119, 136, 0]),
+        ];
+
+        let projected = project_bodies(&bodies, &projection, &render_options);
+        assert_eq!(projected.len(), expected.len());
+
+        for (body, (name, x, y, radius, color)) in projected.iter().zip(expected) {
+            assert_eq!(bodies[body.index].name, name);
+            assert!((body.x - x).abs() < 1.0E-3, "{} x: {}", name, body.x);
+            assert!((body.y - y).abs() < 1.0E-3, "{} y: {}", name, body.y);
+            assert!(
+                (body.radius - radius).abs() < 1.0E-3,
+                "{} radius: {}",
+                name,
+                body.radius
+            );
+            assert_eq!(body.color, color, "{} color", name);
+        }
+    }
+
+    #[test]
+    fn perihelion_is_relative_to_orbital_parent() {
+        let body = |name: &str, mass: f64, position: DVec3, velocity: DVec3| AstronomicalObject {
+            name: String::from(name),
+            ..AstronomicalObject::test_body(mass, position, velocity)
+        };
+
+        // Planet recedes from the sun, so only the moon's distance to the planet has a minimum.
+        // The moon is on the sunward side, otherwise its pull would also point at the sun
+        let sun = body("Sun", 2.0E30, DVec3::ZERO, DVec3::ZERO);
+        let mut planet = body(
+            "Planet",
+            6.0E24,
+            DVec3::new(1.0E11, 0.0, 0.0),
+            DVec3::new(5.0E3, 0.0, 0.0),
+        );
+        let moon = body("Moon", 7.0E22, DVec3::ZERO, DVec3::ZERO);
+        planet.acceleration = -planet.position.normalize() * integration::G * sun.mass / 1.0E22;
+
+        let snapshot = |moon_offset: DVec3, moon_velocity: f64| {
+            let mut moon = moon.clone();
+            moon.position = planet.position + moon_offset;
+            moon.velocity = planet.velocity + DVec3::new(moon_velocity, 0.0, 0.0);
+            moon.acceleration = -moon_offset.normalize() * integration::G * planet.mass
+                / moon_offset.length_squared();
+            Arc::new(vec![sun.clone(), planet.clone(), moon])
+        };
+
+        let mut tracker = PerihelionTracker::default();
+        let approaching = snapshot(DVec3::new(-4.0E8, 1.0E8, 0.0), 1.0E3);
+        tracker.refresh_parents(&approaching);
+        tracker.update(&approaching);
+
+        // The same snapshot painted again is not a new sample
+        let receding = snapshot(DVec3::new(-3.9E8, 1.0E8, 0.0), -1.0E3);
+        tracker.update(&receding);
+        tracker.update(&receding);
+        tracker.update(&snapshot(DVec3::new(-4.0E8, 1.0E8, 0.0), -1.0E3));
+
+        assert_eq!(tracker.marks.len(), 1);
+        assert_eq!(tracker.marks[0].parent, planet.uuid);
+        assert_eq!(tracker.marks[0].offset, DVec3::new(-3.9E8, 1.0E8, 0.0));
+    }
+}